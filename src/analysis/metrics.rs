@@ -0,0 +1,301 @@
+use hpv::{PeerId, Views};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::Write;
+
+/// The overlay properties the HyParView paper evaluates, computed over the active views. Peers
+/// that appear in a view but have no snapshot of their own are ignored.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Metrics {
+    pub nodes: usize,
+    /// The number of nodes having a given in-degree
+    pub in_degree: BTreeMap<usize, usize>,
+    /// The average over all nodes of the fraction of possible links among its neighbours that exist
+    pub clustering_coefficient: f64,
+    /// The average length of the shortest paths between all pairs of nodes that are connected
+    pub average_shortest_path: f64,
+    /// Counted ignoring the direction of links
+    pub connected_components: usize,
+    /// The fraction of links that are reciprocated
+    pub active_view_symmetry: f64,
+}
+
+impl Metrics {
+    pub fn compute(views: &HashMap<PeerId, Views>) -> Metrics {
+        let graph = active_graph(views);
+        Metrics {
+            nodes: graph.len(),
+            in_degree: in_degree(&graph),
+            clustering_coefficient: clustering_coefficient(&graph),
+            average_shortest_path: average_shortest_path(&graph),
+            connected_components: connected_components(&graph),
+            active_view_symmetry: symmetry(&graph),
+        }
+    }
+
+    /// The scalar metrics as a header and a single row
+    pub fn to_csv(&self) -> String {
+        Metrics::csv_header() + &self.to_csv_row()
+    }
+
+    /// Names the columns of `to_csv_row`
+    pub fn csv_header() -> String {
+        String::from(
+            "nodes,clustering_coefficient,average_shortest_path,connected_components,active_view_symmetry\n",
+        )
+    }
+
+    /// The scalar metrics as a single row without header, to append the metrics of successive
+    /// snapshots below one `csv_header`
+    pub fn to_csv_row(&self) -> String {
+        format!(
+            "{},{},{},{},{}\n",
+            self.nodes,
+            self.clustering_coefficient,
+            self.average_shortest_path,
+            self.connected_components,
+            self.active_view_symmetry
+        )
+    }
+
+    pub fn in_degree_csv(&self) -> String {
+        let mut csv = String::from("in_degree,nodes\n");
+        for (degree, count) in self.in_degree.iter() {
+            writeln!(csv, "{},{}", degree, count).unwrap();
+        }
+        csv
+    }
+
+    pub fn to_json(&self) -> String {
+        let in_degree: Vec<String> = self.in_degree
+            .iter()
+            .map(|(degree, count)| format!("\"{}\":{}", degree, count))
+            .collect();
+        format!(
+            "{{\"nodes\":{},\"in_degree\":{{{}}},\"clustering_coefficient\":{},\"average_shortest_path\":{},\"connected_components\":{},\"active_view_symmetry\":{}}}",
+            self.nodes,
+            in_degree.join(","),
+            json_number(self.clustering_coefficient),
+            json_number(self.average_shortest_path),
+            self.connected_components,
+            json_number(self.active_view_symmetry)
+        )
+    }
+}
+
+type Graph = BTreeMap<PeerId, Vec<PeerId>>;
+
+fn active_graph(views: &HashMap<PeerId, Views>) -> Graph {
+    views
+        .iter()
+        .map(|(id, v)| {
+            let neighbours = v.active_view
+                .iter()
+                .map(|p| p.id)
+                .filter(|n| n != id && views.contains_key(n))
+                .collect();
+            (*id, neighbours)
+        })
+        .collect()
+}
+
+fn in_degree(graph: &Graph) -> BTreeMap<usize, usize> {
+    let mut degrees: HashMap<PeerId, usize> = graph.keys().map(|id| (*id, 0)).collect();
+    for neighbours in graph.values() {
+        for n in neighbours {
+            *degrees.get_mut(n).unwrap() += 1;
+        }
+    }
+    let mut distribution = BTreeMap::new();
+    for degree in degrees.values() {
+        *distribution.entry(*degree).or_insert(0) += 1;
+    }
+    distribution
+}
+
+fn clustering_coefficient(graph: &Graph) -> f64 {
+    if graph.is_empty() {
+        return 0.0;
+    }
+    let total: f64 = graph
+        .values()
+        .map(|neighbours| {
+            let k = neighbours.len();
+            if k < 2 {
+                return 0.0;
+            }
+            let links = neighbours
+                .iter()
+                .map(|n| graph[n].iter().filter(|m| neighbours.contains(m)).count())
+                .sum::<usize>();
+            links as f64 / (k * (k - 1)) as f64
+        })
+        .sum();
+    total / graph.len() as f64
+}
+
+fn average_shortest_path(graph: &Graph) -> f64 {
+    let mut total = 0;
+    let mut paths = 0;
+    for start in graph.keys() {
+        let mut distance: HashMap<PeerId, usize> = hashmap!{*start => 0};
+        let mut queue = VecDeque::new();
+        queue.push_back(*start);
+        while let Some(id) = queue.pop_front() {
+            let d = distance[&id];
+            for n in graph[&id].iter() {
+                if !distance.contains_key(n) {
+                    distance.insert(*n, d + 1);
+                    total += d + 1;
+                    paths += 1;
+                    queue.push_back(*n);
+                }
+            }
+        }
+    }
+    if paths == 0 {
+        0.0
+    } else {
+        total as f64 / paths as f64
+    }
+}
+
+fn connected_components(graph: &Graph) -> usize {
+    let mut undirected: HashMap<PeerId, Vec<PeerId>> = HashMap::new();
+    for (id, neighbours) in graph.iter() {
+        undirected.entry(*id).or_insert_with(Vec::new);
+        for n in neighbours {
+            undirected.entry(*id).or_insert_with(Vec::new).push(*n);
+            undirected.entry(*n).or_insert_with(Vec::new).push(*id);
+        }
+    }
+
+    let mut seen: HashSet<PeerId> = HashSet::new();
+    let mut components = 0;
+    for start in graph.keys() {
+        if !seen.insert(*start) {
+            continue;
+        }
+        components += 1;
+        let mut queue = VecDeque::new();
+        queue.push_back(*start);
+        while let Some(id) = queue.pop_front() {
+            for n in undirected[&id].iter() {
+                if seen.insert(*n) {
+                    queue.push_back(*n);
+                }
+            }
+        }
+    }
+    components
+}
+
+fn symmetry(graph: &Graph) -> f64 {
+    let links: usize = graph.values().map(|n| n.len()).sum();
+    if links == 0 {
+        return 1.0;
+    }
+    let reciprocated = graph
+        .iter()
+        .map(|(id, neighbours)| {
+            neighbours
+                .iter()
+                .filter(|n| graph[n].contains(id))
+                .count()
+        })
+        .sum::<usize>();
+    reciprocated as f64 / links as f64
+}
+
+// JSON has no representation for NaN or infinity
+fn json_number(x: f64) -> String {
+    if x.is_finite() {
+        format!("{}", x)
+    } else {
+        String::from("null")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bounded_set::BoundedSet;
+    use hpv::Peer;
+
+    fn peer(i: u8) -> Peer {
+        Peer::new(
+            PeerId::from_bytes([i; 16]),
+            format!("127.0.0.1:{}", 9000 + i as u16).parse().unwrap(),
+        )
+    }
+
+    /// Views where each node lists the given nodes as active
+    fn views(active: &[(u8, &[u8])]) -> HashMap<PeerId, Views> {
+        active
+            .iter()
+            .map(|&(i, neighbours)| {
+                let view = Views {
+                    active_view: BoundedSet::init(8, neighbours.iter().map(|n| peer(*n)).collect()),
+                    passive_view: BoundedSet::new(8),
+                };
+                (peer(i).id, view)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn triangle() {
+        let metrics = Metrics::compute(&views(&[(1, &[2, 3]), (2, &[1, 3]), (3, &[1, 2])]));
+
+        assert_eq!(metrics.nodes, 3);
+        assert_eq!(metrics.in_degree, btreemap!{2 => 3});
+        assert_eq!(metrics.clustering_coefficient, 1.0);
+        assert_eq!(metrics.average_shortest_path, 1.0);
+        assert_eq!(metrics.connected_components, 1);
+        assert_eq!(metrics.active_view_symmetry, 1.0);
+    }
+
+    #[test]
+    fn directed_ring() {
+        let metrics = Metrics::compute(&views(&[(1, &[2]), (2, &[3]), (3, &[4]), (4, &[1])]));
+
+        assert_eq!(metrics.in_degree, btreemap!{1 => 4});
+        assert_eq!(metrics.clustering_coefficient, 0.0);
+        // Each node reaches the others at distances 1, 2 and 3
+        assert_eq!(metrics.average_shortest_path, 2.0);
+        assert_eq!(metrics.connected_components, 1);
+        assert_eq!(metrics.active_view_symmetry, 0.0);
+    }
+
+    #[test]
+    fn separate_components_and_unknown_peers() {
+        // 9 has no snapshot of its own
+        let metrics = Metrics::compute(&views(&[
+            (1, &[2, 9]),
+            (2, &[1]),
+            (3, &[4]),
+            (4, &[]),
+            (5, &[]),
+        ]));
+
+        assert_eq!(metrics.nodes, 5);
+        assert_eq!(metrics.in_degree, btreemap!{0 => 2, 1 => 3});
+        assert_eq!(metrics.connected_components, 3);
+        assert_eq!(metrics.active_view_symmetry, 2.0 / 3.0);
+    }
+
+    #[test]
+    fn export() {
+        let metrics = Metrics::compute(&views(&[(1, &[2]), (2, &[1])]));
+
+        assert_eq!(
+            metrics.to_csv(),
+            "nodes,clustering_coefficient,average_shortest_path,connected_components,active_view_symmetry\n2,0,1,1,1\n"
+        );
+        assert_eq!(metrics.to_csv_row(), "2,0,1,1,1\n");
+        assert_eq!(metrics.in_degree_csv(), "in_degree,nodes\n1,2\n");
+        assert_eq!(
+            metrics.to_json(),
+            "{\"nodes\":2,\"in_degree\":{\"1\":2},\"clustering_coefficient\":0,\"average_shortest_path\":1,\"connected_components\":1,\"active_view_symmetry\":1}"
+        );
+    }
+}
@@ -0,0 +1,7 @@
+//! Offline analysis of overlays, from snapshots of the `Views` of every node
+
+mod metrics;
+pub use self::metrics::*;
+
+mod topology;
+pub use self::topology::*;
@@ -0,0 +1,155 @@
+use hpv::{PeerId, Views};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+/// Renders the views as a Graphviz digraph: an edge points from a node to each peer in its views,
+/// solid for the active view and dashed for the passive view. Nodes and edges are sorted by peer
+/// id, so the output of two snapshots can be diffed.
+pub fn topology_to_dot(views: &HashMap<PeerId, Views>) -> String {
+    let mut dot = String::from("digraph overlay {\n");
+    for (id, addr) in nodes(views) {
+        let label = match addr {
+            Some(addr) => format!("{}\\n{}", short(&id), addr),
+            None => short(&id),
+        };
+        writeln!(dot, "    \"{}\" [label=\"{}\"];", id, label).unwrap();
+    }
+    for (from, to, active) in edges(views) {
+        if active {
+            writeln!(dot, "    \"{}\" -> \"{}\";", from, to).unwrap();
+        } else {
+            writeln!(dot, "    \"{}\" -> \"{}\" [style=dashed];", from, to).unwrap();
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// The same graph as `topology_to_dot`, as a JSON document with one node or edge per line
+pub fn topology_to_json(views: &HashMap<PeerId, Views>) -> String {
+    let nodes: Vec<String> = nodes(views)
+        .into_iter()
+        .map(|(id, addr)| match addr {
+            Some(addr) => format!("    {{\"id\":\"{}\",\"addr\":\"{}\"}}", id, addr),
+            None => format!("    {{\"id\":\"{}\",\"addr\":null}}", id),
+        })
+        .collect();
+    let edges: Vec<String> = edges(views)
+        .into_iter()
+        .map(|(from, to, active)| {
+            let view = if active { "active" } else { "passive" };
+            format!(
+                "    {{\"from\":\"{}\",\"to\":\"{}\",\"view\":\"{}\"}}",
+                from, to, view
+            )
+        })
+        .collect();
+    format!(
+        "{{\n  \"nodes\": [\n{}\n  ],\n  \"edges\": [\n{}\n  ]\n}}\n",
+        nodes.join(",\n"),
+        edges.join(",\n")
+    )
+}
+
+/// Every node with a snapshot or in a view, with its address if any view contains it
+fn nodes(views: &HashMap<PeerId, Views>) -> BTreeMap<PeerId, Option<String>> {
+    let mut nodes: BTreeMap<PeerId, Option<String>> =
+        views.keys().map(|id| (*id, None)).collect();
+    for v in views.values() {
+        for p in v.active_view.iter().chain(v.passive_view.iter()) {
+            nodes.insert(p.id, Some(p.addr.to_string()));
+        }
+    }
+    nodes
+}
+
+/// All (from, to, active) edges, sorted
+fn edges(views: &HashMap<PeerId, Views>) -> Vec<(PeerId, PeerId, bool)> {
+    let sorted: BTreeMap<&PeerId, &Views> = views.iter().collect();
+    let mut edges = Vec::new();
+    for (id, v) in sorted {
+        let active = v.active_view.iter().map(|p| (*id, p.id, true));
+        let passive = v.passive_view.iter().map(|p| (*id, p.id, false));
+        edges.extend(active.chain(passive));
+    }
+    edges
+}
+
+fn short(id: &PeerId) -> String {
+    id.to_string()[..8].to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bounded_set::BoundedSet;
+    use hpv::Peer;
+
+    fn peer(i: u8) -> Peer {
+        Peer::new(
+            PeerId::from_bytes([i; 16]),
+            format!("127.0.0.1:{}", 9000 + i as u16).parse().unwrap(),
+        )
+    }
+
+    fn views() -> HashMap<PeerId, Views> {
+        hashmap!{
+            peer(1).id => Views {
+                active_view: BoundedSet::init(2, hashset!{peer(2)}),
+                passive_view: BoundedSet::init(2, hashset!{peer(3)}),
+            },
+            peer(2).id => Views {
+                active_view: BoundedSet::init(2, hashset!{peer(1)}),
+                passive_view: BoundedSet::new(2),
+            },
+        }
+    }
+
+    #[test]
+    fn dot() {
+        let id = |i| peer(i).id;
+        assert_eq!(
+            topology_to_dot(&views()),
+            format!(
+                "digraph overlay {{
+    \"{0}\" [label=\"01010101\\n127.0.0.1:9001\"];
+    \"{1}\" [label=\"02020202\\n127.0.0.1:9002\"];
+    \"{2}\" [label=\"03030303\\n127.0.0.1:9003\"];
+    \"{0}\" -> \"{1}\";
+    \"{0}\" -> \"{2}\" [style=dashed];
+    \"{1}\" -> \"{0}\";
+}}
+",
+                id(1),
+                id(2),
+                id(3)
+            )
+        );
+    }
+
+    #[test]
+    fn json() {
+        let id = |i| peer(i).id;
+        assert_eq!(
+            topology_to_json(&views()),
+            format!(
+                "{{
+  \"nodes\": [
+    {{\"id\":\"{0}\",\"addr\":\"127.0.0.1:9001\"}},
+    {{\"id\":\"{1}\",\"addr\":\"127.0.0.1:9002\"}},
+    {{\"id\":\"{2}\",\"addr\":\"127.0.0.1:9003\"}}
+  ],
+  \"edges\": [
+    {{\"from\":\"{0}\",\"to\":\"{1}\",\"view\":\"active\"}},
+    {{\"from\":\"{0}\",\"to\":\"{2}\",\"view\":\"passive\"}},
+    {{\"from\":\"{1}\",\"to\":\"{0}\",\"view\":\"active\"}}
+  ]
+}}
+",
+                id(1),
+                id(2),
+                id(3)
+            )
+        );
+    }
+}
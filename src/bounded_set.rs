@@ -1,26 +1,62 @@
 extern crate rand;
 
-use std::collections::HashSet;
+use self::rand::seq;
+use self::rand::Rng;
+use std::collections::{BTreeMap, HashSet};
 use std::fmt::Debug;
 use std::fmt::{Display, Error, Formatter};
 use std::hash::Hash;
-use std::mem;
 
-#[derive(Eq, PartialEq, Debug, Clone)]
-pub struct BoundedSet<E: Hash + Eq + PartialEq> {
+/// Decides which element makes room when an element is inserted into a full `BoundedSet`
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionPolicy {
+    /// Any element, picked uniformly at random
+    Random,
+    /// The element that was inserted first
+    OldestFirst,
+    /// The element that was inserted or touched least recently
+    LeastRecentlyUsed,
+    /// The element with the lowest score, ties broken by insertion order
+    ScoreBased,
+}
+
+// What the eviction policies know about an element
+#[derive(Clone, Debug)]
+struct Entry {
+    inserted: u64,
+    used: u64,
+    // the round in which the element was last inserted or touched
+    used_round: u64,
+    score: i64,
+}
+
+/// A set that holds at most `capacity` elements. Elements are kept in their natural order, so that
+/// iterating and sampling with a seeded RNG is reproducible.
+#[derive(Clone)]
+pub struct BoundedSet<E: Ord + Hash> {
     pub capacity: usize,
-    wraps: HashSet<E>,
+    policy: EvictionPolicy,
+    wraps: BTreeMap<E, Entry>,
+    // ticks with every insert and touch, ordering the entries in time
+    clock: u64,
+    // counts the calls to `tick`, the unit in which the ages of elements are measured
+    round: u64,
 }
 
-impl<E: Eq + PartialEq + Hash> BoundedSet<E> {
+impl<E: Ord + Hash> BoundedSet<E> {
     pub fn new(capacity: usize) -> BoundedSet<E> {
-        BoundedSet::init(capacity, HashSet::with_capacity(capacity))
+        BoundedSet::init(capacity, HashSet::new())
+    }
+
+    pub fn with_policy(capacity: usize, policy: EvictionPolicy) -> BoundedSet<E> {
+        let mut set = BoundedSet::new(capacity);
+        set.policy = policy;
+        set
     }
 
     pub fn single(capacity: usize, singleton: E) -> BoundedSet<E> {
-        let mut set: HashSet<E> = HashSet::with_capacity(capacity);
-        set.insert(singleton);
-        BoundedSet::init(capacity, set)
+        BoundedSet::init(capacity, hashset!{singleton})
     }
 
     pub fn init(capacity: usize, wraps: HashSet<E>) -> BoundedSet<E> {
@@ -28,91 +64,252 @@ impl<E: Eq + PartialEq + Hash> BoundedSet<E> {
             panic!("Capacity of this new BoundedSet exceeds the size of the initial state")
         }
 
-        BoundedSet {
+        // Sort the initial state, so that insertion order does not depend on its hashing
+        let mut elems: Vec<E> = wraps.into_iter().collect();
+        elems.sort();
+        let mut set = BoundedSet {
             capacity: capacity,
-            wraps: wraps,
+            policy: EvictionPolicy::Random,
+            wraps: BTreeMap::new(),
+            clock: 0,
+            round: 0,
+        };
+        for e in elems {
+            set.insert(e);
         }
+        set
     }
 
-    pub fn set_capacity(&mut self, new_capacity: usize) {
-        if self.wraps.len() <= new_capacity {
-            self.capacity = new_capacity;
-        } else {
-            panic!("New capacity exceeds current set size");
+    pub fn policy(&self) -> EvictionPolicy {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: EvictionPolicy) {
+        self.policy = policy;
+    }
+
+    /// Changes the capacity, evicting elements according to the policy until they fit. Returns the
+    /// evicted elements.
+    pub fn set_capacity<R: Rng + ?Sized>(&mut self, new_capacity: usize, rng: &mut R) -> Vec<E>
+    where
+        E: Clone,
+    {
+        let mut evicted = Vec::new();
+        while self.wraps.len() > new_capacity {
+            match self.evict(rng) {
+                Some(e) => evicted.push(e),
+                None => break,
+            }
         }
+        self.capacity = new_capacity;
+        evicted
     }
 
     pub fn is_full(&self) -> bool {
         self.capacity == self.wraps.len()
     }
 
-    pub fn sample_one(&self) -> Option<&E> {
-        // Sets are somewhat randomly distributed
-        // FIXME: a bias will exist if the same hash is used consistently
-        self.wraps.iter().next()
+    /// Picks an element uniformly at random
+    pub fn sample_one<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<&E> {
+        if self.wraps.is_empty() {
+            None
+        } else {
+            let index = rng.gen_range(0, self.wraps.len());
+            self.wraps.keys().nth(index)
+        }
     }
 
-    pub fn sample(&self, max_size: usize) -> HashSet<&E> {
-        self.wraps.iter().take(max_size).collect::<HashSet<&E>>()
+    /// Picks `max_size` distinct elements uniformly at random, or all elements if there are fewer
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R, max_size: usize) -> HashSet<&E> {
+        match seq::sample_iter(rng, self.wraps.keys(), max_size) {
+            Ok(sample) => sample.into_iter().collect(),
+            Err(all) => all.into_iter().collect(),
+        }
     }
 
     pub fn contains(&self, elem: &E) -> bool {
-        self.wraps.contains(elem)
+        self.wraps.contains_key(elem)
     }
 
+    /// Inserts `elem` if there is room for it. Inserting an element that is already present only
+    /// touches it.
     pub fn insert(&mut self, elem: E) -> bool {
-        if self.capacity > self.wraps.len() {
-            self.wraps.insert(elem)
+        if self.wraps.contains_key(&elem) {
+            self.touch(&elem);
+            false
+        } else if self.capacity > self.wraps.len() {
+            self.clock += 1;
+            let entry = Entry {
+                inserted: self.clock,
+                used: self.clock,
+                used_round: self.round,
+                score: 0,
+            };
+            self.wraps.insert(elem, entry);
+            true
         } else {
             false
         }
     }
 
+    /// Inserts `elem`, first evicting an element according to the policy if the set is full. Returns
+    /// the evicted element, if any.
+    pub fn insert_evicting<R: Rng + ?Sized>(&mut self, elem: E, rng: &mut R) -> Option<E>
+    where
+        E: Clone,
+    {
+        let evicted = if self.is_full() && !self.contains(&elem) {
+            self.evict(rng)
+        } else {
+            None
+        };
+        self.insert(elem);
+        evicted
+    }
+
+    /// Removes the element that the policy picks to make room
+    pub fn evict<R: Rng + ?Sized>(&mut self, rng: &mut R) -> Option<E>
+    where
+        E: Clone,
+    {
+        let victim = self.eviction_order(rng).into_iter().next().cloned();
+        if let Some(ref victim) = victim {
+            self.wraps.remove(victim);
+        }
+        victim
+    }
+
+    /// All elements, the first to be evicted first
+    pub fn eviction_order<R: Rng + ?Sized>(&self, rng: &mut R) -> Vec<&E> {
+        let mut order: Vec<(&E, &Entry)> = self.wraps.iter().collect();
+        match self.policy {
+            EvictionPolicy::Random => rng.shuffle(&mut order),
+            EvictionPolicy::OldestFirst => order.sort_by_key(|&(_, entry)| entry.inserted),
+            EvictionPolicy::LeastRecentlyUsed => order.sort_by_key(|&(_, entry)| entry.used),
+            EvictionPolicy::ScoreBased => {
+                order.sort_by_key(|&(_, entry)| (entry.score, entry.inserted))
+            }
+        }
+        order.into_iter().map(|(e, _)| e).collect()
+    }
+
+    /// Marks the element as used, which matters to `LeastRecentlyUsed` eviction
+    pub fn touch(&mut self, elem: &E) {
+        if let Some(entry) = self.wraps.get_mut(elem) {
+            self.clock += 1;
+            entry.used = self.clock;
+            entry.used_round = self.round;
+        }
+    }
+
+    /// Starts a new round, making every element one round older
+    pub fn tick(&mut self) {
+        self.round += 1;
+    }
+
+    /// The number of rounds since the element was last inserted or touched
+    pub fn age(&self, elem: &E) -> Option<u64> {
+        self.wraps.get(elem).map(|entry| self.round - entry.used_round)
+    }
+
+    /// Sets the score that `ScoreBased` eviction goes by; elements start at 0
+    pub fn set_score(&mut self, elem: &E, score: i64) {
+        if let Some(entry) = self.wraps.get_mut(elem) {
+            entry.score = score;
+        }
+    }
+
     pub fn remove(&mut self, elem: &E) -> bool {
-        self.wraps.remove(elem)
+        self.wraps.remove(elem).is_some()
     }
 
     pub fn len(&self) -> usize {
         self.wraps.len()
     }
 
-    pub fn bounded_union(&mut self, to_merge: &HashSet<E>, drop_priority: &HashSet<E>)
-    where
+    /// Merges `to_merge` into this set. Room is made by evicting the elements in `drop_priority`
+    /// first and the others next, each in the order of the policy. Merged elements that are already
+    /// present are kept.
+    pub fn bounded_union<R: Rng + ?Sized>(
+        &mut self,
+        to_merge: &HashSet<E>,
+        drop_priority: &HashSet<E>,
+        rng: &mut R,
+    ) where
         E: Clone,
     {
-        let mut replacement: HashSet<E> = HashSet::new();
-        mem::swap(&mut self.wraps, &mut replacement);
-        let iter = to_merge
+        // Sort the foreign set, its iteration order differs between runs
+        let mut fresh: Vec<E> = to_merge
             .iter()
-            .chain(replacement.difference(drop_priority))
-            .chain(drop_priority.intersection(&replacement))
-            .cloned();
-
-        for e in iter {
-            if self.capacity > self.len() {
-                self.wraps.insert(e);
-            } else {
-                return;
+            .filter(|e| !self.contains(e))
+            .cloned()
+            .collect();
+        fresh.sort();
+
+        // The sort is stable, so the policy decides within each group
+        let mut order: Vec<E> = self.eviction_order(rng).into_iter().cloned().collect();
+        order.sort_by_key(|e| (to_merge.contains(e), !drop_priority.contains(e)));
+        let mut surplus = (self.len() + fresh.len()).saturating_sub(self.capacity);
+        for e in order {
+            if surplus == 0 || to_merge.contains(&e) {
+                break;
             }
+            self.wraps.remove(&e);
+            surplus -= 1;
         }
+
+        for e in fresh {
+            self.insert(e);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &E> {
+        self.wraps.keys()
     }
 
     pub fn for_each<F>(&self, f: F)
     where
         F: FnMut(&E) -> (),
     {
-        self.wraps.iter().for_each(f);
+        self.wraps.keys().for_each(f);
     }
 
     pub fn as_set(&self) -> HashSet<E>
     where
         E: Clone,
     {
-        self.wraps.clone()
+        self.wraps.keys().cloned().collect()
+    }
+}
+
+/// Sets are equal when they have the same capacity and elements, regardless of their policy
+impl<E: Ord + Hash> PartialEq for BoundedSet<E> {
+    fn eq(&self, other: &BoundedSet<E>) -> bool {
+        self.capacity == other.capacity && self.wraps.keys().eq(other.wraps.keys())
     }
 }
 
-impl<E: Debug + Display + Eq + Hash> Display for BoundedSet<E> {
+impl<E: Ord + Hash> Eq for BoundedSet<E> {}
+
+impl<E: Debug + Ord + Hash> Debug for BoundedSet<E> {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        fmt.debug_struct("BoundedSet")
+            .field("capacity", &self.capacity)
+            .field("policy", &self.policy)
+            .field("wraps", &DebugKeys(&self.wraps))
+            .finish()
+    }
+}
+
+struct DebugKeys<'a, E: 'a>(&'a BTreeMap<E, Entry>);
+
+impl<'a, E: Debug> Debug for DebugKeys<'a, E> {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        fmt.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+impl<E: Debug + Display + Ord + Hash> Display for BoundedSet<E> {
     fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
         Debug::fmt(self, fmt)
     }
@@ -121,8 +318,10 @@ impl<E: Debug + Display + Eq + Hash> Display for BoundedSet<E> {
 #[cfg(test)]
 mod test {
 
-    use super::BoundedSet;
-    use std::collections::HashSet;
+    use super::rand::rngs::SmallRng;
+    use super::rand::{thread_rng, SeedableRng};
+    use super::{BoundedSet, EvictionPolicy};
+    use std::collections::{HashMap, HashSet};
 
     const TEST_ELEM: u32 = 1;
 
@@ -158,35 +357,83 @@ mod test {
     #[test]
     fn empty_random_element() {
         let set: BoundedSet<u32> = BoundedSet::new(1);
-        assert!(set.sample_one().is_none());
+        assert!(set.sample_one(&mut thread_rng()).is_none());
     }
 
     #[test]
     fn singleton_random_element() {
         let set: BoundedSet<u32> = BoundedSet::single(1, TEST_ELEM);
-        assert!(set.sample_one().unwrap() == &TEST_ELEM);
+        assert!(set.sample_one(&mut thread_rng()).unwrap() == &TEST_ELEM);
     }
 
     #[test]
     fn empty_sample() {
         let set: BoundedSet<u32> = BoundedSet::new(1);
-        assert!(set.sample(10).is_empty());
+        assert!(set.sample(&mut thread_rng(), 10).is_empty());
     }
 
     #[test]
     fn bounded_sample() {
         let set: BoundedSet<u32> = BoundedSet::single(1, TEST_ELEM);
-        assert!(set.sample(0).is_empty());
+        assert!(set.sample(&mut thread_rng(), 0).is_empty());
     }
 
     #[test]
     fn max_sample() {
         let set: BoundedSet<u32> = BoundedSet::single(1, TEST_ELEM);
-        let sample = set.sample(10);
+        let sample = set.sample(&mut thread_rng(), 10);
         assert_eq!(sample.len(), 1);
         assert!(sample.contains(&TEST_ELEM));
     }
 
+    #[test]
+    fn uniform_random_element() {
+        let mut rng = SmallRng::from_seed([7; 16]);
+        let set: BoundedSet<u32> = BoundedSet::init(4, (0..4).collect());
+        let mut counts: HashMap<u32, usize> = HashMap::new();
+        for _ in 0..4000 {
+            *counts.entry(*set.sample_one(&mut rng).unwrap()).or_insert(0) += 1;
+        }
+
+        assert_eq!(counts.len(), 4);
+        assert!(counts.values().all(|c| *c > 850 && *c < 1150));
+    }
+
+    #[test]
+    fn uniform_sample() {
+        let mut rng = SmallRng::from_seed([7; 16]);
+        let set: BoundedSet<u32> = BoundedSet::init(8, (0..8).collect());
+        let mut counts: HashMap<u32, usize> = HashMap::new();
+        for _ in 0..4000 {
+            let sample = set.sample(&mut rng, 2);
+            assert_eq!(sample.len(), 2);
+            sample.into_iter().for_each(|e| *counts.entry(*e).or_insert(0) += 1);
+        }
+
+        // every element should be picked in roughly a quarter of the samples
+        assert_eq!(counts.len(), 8);
+        assert!(counts.values().all(|c| *c > 850 && *c < 1150));
+    }
+
+    #[test]
+    fn seeded_sample_ignores_insertion_order() {
+        let mut ascending: BoundedSet<u32> = BoundedSet::new(16);
+        let mut descending: BoundedSet<u32> = BoundedSet::new(16);
+        (0..16).for_each(|e| {
+            ascending.insert(e);
+        });
+        (0..16).rev().for_each(|e| {
+            descending.insert(e);
+        });
+
+        let mut rng1 = SmallRng::from_seed([3; 16]);
+        let mut rng2 = SmallRng::from_seed([3; 16]);
+        for _ in 0..100 {
+            assert_eq!(ascending.sample_one(&mut rng1), descending.sample_one(&mut rng2));
+            assert_eq!(ascending.sample(&mut rng1, 3), descending.sample(&mut rng2, 3));
+        }
+    }
+
     #[test]
     fn contains() {
         let set = BoundedSet::init(1, singleton_set());
@@ -224,7 +471,7 @@ mod test {
         let mut set1: BoundedSet<u32> = BoundedSet::single(10, TEST_ELEM);
         let mut set2: HashSet<u32> = HashSet::new();
         set2.insert(2);
-        set1.bounded_union(&set2, &HashSet::new());
+        set1.bounded_union(&set2, &HashSet::new(), &mut thread_rng());
 
         assert!(set1.contains(&TEST_ELEM));
         assert!(set1.contains(&2));
@@ -238,8 +485,156 @@ mod test {
         let mut drop_prio: HashSet<u32> = HashSet::new();
         drop_prio.insert(TEST_ELEM);
 
-        set.bounded_union(&to_merge, &drop_prio);
+        set.bounded_union(&to_merge, &drop_prio, &mut thread_rng());
         assert_eq!(set.wraps.len(), 1);
         assert!(set.contains(&2));
     }
+
+    #[test]
+    fn bounded_union_drops_least_recently_used_first() {
+        let mut set: BoundedSet<u32> = BoundedSet::with_policy(3, EvictionPolicy::LeastRecentlyUsed);
+        (0..3).for_each(|e| {
+            set.insert(e);
+        });
+        set.touch(&0);
+        let mut to_merge: HashSet<u32> = HashSet::new();
+        to_merge.insert(3);
+
+        set.bounded_union(&to_merge, &HashSet::new(), &mut thread_rng());
+        assert_eq!(set.as_set(), vec![0, 2, 3].into_iter().collect());
+    }
+
+    #[test]
+    fn bounded_union_drop_priority_precedes_policy() {
+        let mut set: BoundedSet<u32> = BoundedSet::with_policy(2, EvictionPolicy::OldestFirst);
+        (0..2).for_each(|e| {
+            set.insert(e);
+        });
+        let mut drop_prio: HashSet<u32> = HashSet::new();
+        drop_prio.insert(1);
+        let mut to_merge: HashSet<u32> = HashSet::new();
+        to_merge.insert(2);
+
+        set.bounded_union(&to_merge, &drop_prio, &mut thread_rng());
+        assert_eq!(set.as_set(), vec![0, 2].into_iter().collect());
+    }
+
+    #[test]
+    fn bounded_union_keeps_merged_elements() {
+        let mut set: BoundedSet<u32> = BoundedSet::with_policy(2, EvictionPolicy::OldestFirst);
+        (0..2).for_each(|e| {
+            set.insert(e);
+        });
+        let to_merge: HashSet<u32> = vec![0, 2].into_iter().collect();
+
+        set.bounded_union(&to_merge, &HashSet::new(), &mut thread_rng());
+        assert_eq!(set.as_set(), to_merge);
+    }
+
+    #[test]
+    fn insert_evicting_only_when_full() {
+        let mut set: BoundedSet<u32> = BoundedSet::new(1);
+        assert_eq!(set.insert_evicting(TEST_ELEM, &mut thread_rng()), None);
+        assert_eq!(set.insert_evicting(TEST_ELEM, &mut thread_rng()), None);
+        assert_eq!(set.insert_evicting(2, &mut thread_rng()), Some(TEST_ELEM));
+        assert_eq!(set.as_set(), vec![2].into_iter().collect());
+    }
+
+    #[test]
+    fn evict_random() {
+        let mut rng = SmallRng::from_seed([7; 16]);
+        let mut counts: HashMap<u32, usize> = HashMap::new();
+        for _ in 0..4000 {
+            let mut set: BoundedSet<u32> = BoundedSet::init(4, (0..4).collect());
+            let evicted = set.insert_evicting(4, &mut rng).unwrap();
+            *counts.entry(evicted).or_insert(0) += 1;
+        }
+
+        assert_eq!(counts.len(), 4);
+        assert!(counts.values().all(|c| *c > 850 && *c < 1150));
+    }
+
+    #[test]
+    fn evict_oldest_first() {
+        let mut set: BoundedSet<u32> = BoundedSet::with_policy(3, EvictionPolicy::OldestFirst);
+        vec![2, 0, 1].into_iter().for_each(|e| {
+            set.insert(e);
+        });
+        set.touch(&2);
+
+        assert_eq!(set.insert_evicting(3, &mut thread_rng()), Some(2));
+        assert_eq!(set.insert_evicting(4, &mut thread_rng()), Some(0));
+    }
+
+    #[test]
+    fn evict_least_recently_used() {
+        let mut set: BoundedSet<u32> = BoundedSet::with_policy(3, EvictionPolicy::LeastRecentlyUsed);
+        vec![2, 0, 1].into_iter().for_each(|e| {
+            set.insert(e);
+        });
+        set.touch(&2);
+        // Inserting a present element counts as using it
+        set.insert(0);
+
+        assert_eq!(set.insert_evicting(3, &mut thread_rng()), Some(1));
+        assert_eq!(set.insert_evicting(4, &mut thread_rng()), Some(2));
+    }
+
+    #[test]
+    fn evict_lowest_score() {
+        let mut set: BoundedSet<u32> = BoundedSet::with_policy(3, EvictionPolicy::ScoreBased);
+        (0..3).for_each(|e| {
+            set.insert(e);
+        });
+        set.set_score(&0, 5);
+        set.set_score(&1, -1);
+
+        assert_eq!(set.insert_evicting(3, &mut thread_rng()), Some(1));
+        // Ties go by insertion order
+        assert_eq!(set.insert_evicting(4, &mut thread_rng()), Some(2));
+        assert_eq!(set.insert_evicting(5, &mut thread_rng()), Some(3));
+    }
+
+    #[test]
+    fn grow_capacity() {
+        let mut set: BoundedSet<u32> = BoundedSet::single(1, TEST_ELEM);
+        assert!(set.set_capacity(2, &mut thread_rng()).is_empty());
+        assert!(set.insert(2));
+    }
+
+    #[test]
+    fn shrink_capacity_evicting_surplus() {
+        let mut set: BoundedSet<u32> = BoundedSet::with_policy(4, EvictionPolicy::OldestFirst);
+        (0..4).for_each(|e| {
+            set.insert(e);
+        });
+
+        assert_eq!(set.set_capacity(1, &mut thread_rng()), vec![0, 1, 2]);
+        assert_eq!(set.capacity, 1);
+        assert_eq!(set.as_set(), vec![3].into_iter().collect());
+    }
+
+    #[test]
+    fn age_by_rounds_since_last_use() {
+        let mut set: BoundedSet<u32> = BoundedSet::new(3);
+        set.insert(0);
+        set.tick();
+        set.insert(1);
+        set.insert(2);
+        set.tick();
+        set.tick();
+        set.touch(&1);
+
+        assert_eq!(set.age(&0), Some(3));
+        assert_eq!(set.age(&1), Some(0));
+        assert_eq!(set.age(&2), Some(2));
+        assert_eq!(set.age(&3), None);
+    }
+
+    #[test]
+    fn equality_ignores_policy() {
+        let set1: BoundedSet<u32> = BoundedSet::with_policy(1, EvictionPolicy::ScoreBased);
+        let set2: BoundedSet<u32> = BoundedSet::new(1);
+        assert_eq!(set1, set2);
+    }
 }
@@ -0,0 +1,599 @@
+//! Binary wire format of `HpvMsg`. Every frame is a 4-byte big-endian length, followed by the
+//! protocol version and the encoded message. Integers are big-endian, peers are encoded as their
+//! 16-byte id followed by an address family tag, the IP octets and the port.
+
+use super::{HpvMsg, Peer, PeerId};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::io::ErrorKind::{InvalidData, InvalidInput};
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// The version of the wire format produced by this module
+pub const PROTOCOL_VERSION: u8 = 4;
+
+/// Frames larger than this are rejected rather than buffered
+pub const MAX_FRAME_LEN: usize = 1 << 20;
+
+const TAG_JOIN: u8 = 0;
+const TAG_FORWARD_JOIN: u8 = 1;
+const TAG_NEIGHBOUR: u8 = 2;
+const TAG_NEIGHBOUR_REPLY: u8 = 3;
+const TAG_SHUFFLE: u8 = 4;
+const TAG_SHUFFLE_REPLY: u8 = 5;
+const TAG_DISCONNECT: u8 = 6;
+const TAG_HEARTBEAT: u8 = 7;
+const TAG_HANDOFF: u8 = 8;
+const TAG_JOIN_REPLY: u8 = 9;
+const TAG_PROBE: u8 = 10;
+const TAG_PROBE_REPLY: u8 = 11;
+const TAG_SIZE_SKETCH: u8 = 12;
+
+const TAG_IPV4: u8 = 4;
+const TAG_IPV6: u8 = 6;
+
+#[derive(Debug)]
+pub enum EncodeError {
+    /// The encoded message would exceed `MAX_FRAME_LEN`, so receivers would reject it
+    FrameTooLarge(usize),
+    /// The time-to-live does not fit the 32 bits it is encoded in
+    TtlOverflow(usize),
+    Io(io::Error),
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    UnsupportedVersion(u8),
+    UnknownTag(u8),
+    InvalidBool(u8),
+    InvalidAddressFamily(u8),
+    /// The message ended before all of its fields were read
+    Truncated,
+    /// The number of bytes left after the message was fully read
+    TrailingBytes(usize),
+    FrameTooLarge(usize),
+    Io(io::Error),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EncodeError::FrameTooLarge(n) => write!(f, "Frame of {} bytes exceeds maximum", n),
+            EncodeError::TtlOverflow(ttl) => write!(f, "Time-to-live {} does not fit 32 bits", ttl),
+            EncodeError::Io(e) => write!(f, "I/O error while writing frame: {}", e),
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnsupportedVersion(v) => write!(f, "Unsupported protocol version {}", v),
+            DecodeError::UnknownTag(t) => write!(f, "Unknown message tag {}", t),
+            DecodeError::InvalidBool(b) => write!(f, "Invalid boolean {}", b),
+            DecodeError::InvalidAddressFamily(a) => write!(f, "Invalid address family {}", a),
+            DecodeError::Truncated => write!(f, "Message truncated"),
+            DecodeError::TrailingBytes(n) => write!(f, "{} trailing bytes after message", n),
+            DecodeError::FrameTooLarge(n) => write!(f, "Frame of {} bytes exceeds maximum", n),
+            DecodeError::Io(e) => write!(f, "I/O error while reading frame: {}", e),
+        }
+    }
+}
+
+impl Error for EncodeError {}
+
+impl Error for DecodeError {}
+
+impl From<io::Error> for EncodeError {
+    fn from(e: io::Error) -> EncodeError {
+        EncodeError::Io(e)
+    }
+}
+
+impl From<io::Error> for DecodeError {
+    fn from(e: io::Error) -> DecodeError {
+        DecodeError::Io(e)
+    }
+}
+
+impl From<EncodeError> for io::Error {
+    fn from(e: EncodeError) -> io::Error {
+        match e {
+            EncodeError::Io(e) => e,
+            e => io::Error::new(InvalidInput, e.to_string()),
+        }
+    }
+}
+
+impl From<DecodeError> for io::Error {
+    fn from(e: DecodeError) -> io::Error {
+        match e {
+            DecodeError::Io(e) => e,
+            e => io::Error::new(InvalidData, e.to_string()),
+        }
+    }
+}
+
+/// Writes `msg` as a single frame
+pub fn write_frame<W: Write>(w: &mut W, msg: &HpvMsg) -> Result<(), EncodeError> {
+    let payload = encode(msg)?;
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    put_u32(&mut frame, payload.len() as u32);
+    frame.extend_from_slice(&payload);
+    w.write_all(&frame)?;
+    Ok(())
+}
+
+/// Reads the next frame written by `write_frame`
+pub fn read_frame<R: Read>(r: &mut R) -> Result<HpvMsg, DecodeError> {
+    let mut len = [0u8; 4];
+    r.read_exact(&mut len)?;
+    let len = Reader::new(&len).u32()? as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(DecodeError::FrameTooLarge(len));
+    }
+    let mut payload = vec![0u8; len];
+    r.read_exact(&mut payload)?;
+    decode(&payload)
+}
+
+/// Encodes `msg`, prefixed with the protocol version. Messages that `read_frame` would reject for
+/// their size are refused.
+pub fn encode(msg: &HpvMsg) -> Result<Vec<u8>, EncodeError> {
+    let mut buf = vec![PROTOCOL_VERSION];
+    match msg {
+        HpvMsg::Join(p) => {
+            buf.push(TAG_JOIN);
+            put_peer(&mut buf, p);
+        }
+        HpvMsg::ForwardJoin {
+            joining,
+            forwarder,
+            ttl,
+        } => {
+            buf.push(TAG_FORWARD_JOIN);
+            put_peer(&mut buf, joining);
+            put_peer(&mut buf, forwarder);
+            put_ttl(&mut buf, *ttl)?;
+        }
+        HpvMsg::Neighbour { id, peer, prio } => {
+            buf.push(TAG_NEIGHBOUR);
+            put_u32(&mut buf, *id);
+            put_peer(&mut buf, peer);
+            buf.push(*prio as u8);
+        }
+        HpvMsg::NeighbourReply { id, peer, accepted } => {
+            buf.push(TAG_NEIGHBOUR_REPLY);
+            put_u32(&mut buf, *id);
+            put_peer(&mut buf, peer);
+            buf.push(*accepted as u8);
+        }
+        HpvMsg::Shuffle {
+            id,
+            origin,
+            exchange,
+            ttl,
+        } => {
+            buf.push(TAG_SHUFFLE);
+            put_u32(&mut buf, *id);
+            put_peer(&mut buf, origin);
+            put_peers(&mut buf, exchange);
+            put_ttl(&mut buf, *ttl)?;
+        }
+        HpvMsg::ShuffleReply(id, peers) => {
+            buf.push(TAG_SHUFFLE_REPLY);
+            put_u32(&mut buf, *id);
+            put_peers(&mut buf, peers);
+        }
+        HpvMsg::Disconnect { peer, alive } => {
+            buf.push(TAG_DISCONNECT);
+            put_peer(&mut buf, peer);
+            buf.push(*alive as u8);
+        }
+        HpvMsg::Heartbeat(p) => {
+            buf.push(TAG_HEARTBEAT);
+            put_peer(&mut buf, p);
+        }
+        HpvMsg::Handoff(peers) => {
+            buf.push(TAG_HANDOFF);
+            put_peers(&mut buf, peers);
+        }
+        HpvMsg::JoinReply(p) => {
+            buf.push(TAG_JOIN_REPLY);
+            put_peer(&mut buf, p);
+        }
+        HpvMsg::Probe(p) => {
+            buf.push(TAG_PROBE);
+            put_peer(&mut buf, p);
+        }
+        HpvMsg::ProbeReply(p) => {
+            buf.push(TAG_PROBE_REPLY);
+            put_peer(&mut buf, p);
+        }
+        HpvMsg::SizeSketch { epoch, hashes } => {
+            buf.push(TAG_SIZE_SKETCH);
+            put_u64(&mut buf, *epoch);
+            put_u32(&mut buf, hashes.len() as u32);
+            for h in hashes {
+                put_u64(&mut buf, *h);
+            }
+        }
+    }
+    // Lengths that overflow their 32 bits imply a frame far beyond the maximum
+    if buf.len() > MAX_FRAME_LEN {
+        return Err(EncodeError::FrameTooLarge(buf.len()));
+    }
+    Ok(buf)
+}
+
+/// Decodes a message produced by `encode`
+pub fn decode(buf: &[u8]) -> Result<HpvMsg, DecodeError> {
+    let mut r = Reader::new(buf);
+    match r.u8()? {
+        PROTOCOL_VERSION => {}
+        v => return Err(DecodeError::UnsupportedVersion(v)),
+    }
+    let msg = match r.u8()? {
+        TAG_JOIN => HpvMsg::Join(r.peer()?),
+        TAG_FORWARD_JOIN => HpvMsg::ForwardJoin {
+            joining: r.peer()?,
+            forwarder: r.peer()?,
+            ttl: r.u32()? as usize,
+        },
+        TAG_NEIGHBOUR => HpvMsg::Neighbour {
+            id: r.u32()?,
+            peer: r.peer()?,
+            prio: r.bool()?,
+        },
+        TAG_NEIGHBOUR_REPLY => HpvMsg::NeighbourReply {
+            id: r.u32()?,
+            peer: r.peer()?,
+            accepted: r.bool()?,
+        },
+        TAG_SHUFFLE => HpvMsg::Shuffle {
+            id: r.u32()?,
+            origin: r.peer()?,
+            exchange: r.peers()?,
+            ttl: r.u32()? as usize,
+        },
+        TAG_SHUFFLE_REPLY => HpvMsg::ShuffleReply(r.u32()?, r.peers()?),
+        TAG_DISCONNECT => HpvMsg::Disconnect {
+            peer: r.peer()?,
+            alive: r.bool()?,
+        },
+        TAG_HEARTBEAT => HpvMsg::Heartbeat(r.peer()?),
+        TAG_HANDOFF => HpvMsg::Handoff(r.peers()?),
+        TAG_JOIN_REPLY => HpvMsg::JoinReply(r.peer()?),
+        TAG_PROBE => HpvMsg::Probe(r.peer()?),
+        TAG_PROBE_REPLY => HpvMsg::ProbeReply(r.peer()?),
+        TAG_SIZE_SKETCH => HpvMsg::SizeSketch {
+            epoch: r.u64()?,
+            hashes: r.u64s()?,
+        },
+        tag => return Err(DecodeError::UnknownTag(tag)),
+    };
+    if r.remaining() > 0 {
+        return Err(DecodeError::TrailingBytes(r.remaining()));
+    }
+    Ok(msg)
+}
+
+fn put_u16(buf: &mut Vec<u8>, v: u16) {
+    buf.push((v >> 8) as u8);
+    buf.push(v as u8);
+}
+
+fn put_u32(buf: &mut Vec<u8>, v: u32) {
+    put_u16(buf, (v >> 16) as u16);
+    put_u16(buf, v as u16);
+}
+
+fn put_u64(buf: &mut Vec<u8>, v: u64) {
+    put_u32(buf, (v >> 32) as u32);
+    put_u32(buf, v as u32);
+}
+
+fn put_ttl(buf: &mut Vec<u8>, ttl: usize) -> Result<(), EncodeError> {
+    if ttl > u32::max_value() as usize {
+        return Err(EncodeError::TtlOverflow(ttl));
+    }
+    put_u32(buf, ttl as u32);
+    Ok(())
+}
+
+fn put_peer(buf: &mut Vec<u8>, peer: &Peer) {
+    buf.extend_from_slice(peer.id.as_bytes());
+    match peer.addr.ip() {
+        IpAddr::V4(ip) => {
+            buf.push(TAG_IPV4);
+            buf.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.push(TAG_IPV6);
+            buf.extend_from_slice(&ip.octets());
+        }
+    }
+    put_u16(buf, peer.addr.port());
+}
+
+fn put_peers(buf: &mut Vec<u8>, peers: &HashSet<Peer>) {
+    put_u32(buf, peers.len() as u32);
+    for p in peers {
+        put_peer(buf, p);
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Reader<'a> {
+        Reader { buf: buf, pos: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        if self.remaining() < n {
+            return Err(DecodeError::Truncated);
+        }
+        let bytes = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DecodeError> {
+        let b = self.bytes(2)?;
+        Ok((b[0] as u16) << 8 | b[1] as u16)
+    }
+
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        let high = self.u16()? as u32;
+        let low = self.u16()? as u32;
+        Ok(high << 16 | low)
+    }
+
+    fn u64(&mut self) -> Result<u64, DecodeError> {
+        let high = self.u32()? as u64;
+        let low = self.u32()? as u64;
+        Ok(high << 32 | low)
+    }
+
+    fn u64s(&mut self) -> Result<Vec<u64>, DecodeError> {
+        let len = self.u32()? as usize;
+        let mut values = Vec::new();
+        for _ in 0..len {
+            values.push(self.u64()?);
+        }
+        Ok(values)
+    }
+
+    fn bool(&mut self) -> Result<bool, DecodeError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            b => Err(DecodeError::InvalidBool(b)),
+        }
+    }
+
+    fn peer(&mut self) -> Result<Peer, DecodeError> {
+        let mut id = [0u8; 16];
+        id.copy_from_slice(self.bytes(16)?);
+        let ip = match self.u8()? {
+            TAG_IPV4 => {
+                let b = self.bytes(4)?;
+                IpAddr::V4(Ipv4Addr::new(b[0], b[1], b[2], b[3]))
+            }
+            TAG_IPV6 => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(self.bytes(16)?);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            family => return Err(DecodeError::InvalidAddressFamily(family)),
+        };
+        let port = self.u16()?;
+        Ok(Peer::new(PeerId::from_bytes(id), SocketAddr::new(ip, port)))
+    }
+
+    fn peers(&mut self) -> Result<HashSet<Peer>, DecodeError> {
+        let len = self.u32()? as usize;
+        let mut peers = HashSet::new();
+        for _ in 0..len {
+            peers.insert(self.peer()?);
+        }
+        Ok(peers)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate rand;
+
+    use self::rand::Rng;
+    use super::*;
+    use quickcheck::{Arbitrary, Gen};
+
+    fn peer() -> Peer {
+        Peer::new(PeerId::random(), "127.0.0.1:4000".parse().unwrap())
+    }
+
+    impl Arbitrary for Peer {
+        fn arbitrary<G: Gen>(g: &mut G) -> Peer {
+            let ip = if g.gen() {
+                IpAddr::V4(Ipv4Addr::from(g.gen::<u32>()))
+            } else {
+                let mut octets = [0u8; 16];
+                g.fill(&mut octets);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            };
+            Peer::new(PeerId::random_from(g), SocketAddr::new(ip, g.gen()))
+        }
+    }
+
+    impl Arbitrary for HpvMsg {
+        fn arbitrary<G: Gen>(g: &mut G) -> HpvMsg {
+            match g.gen_range(0, 13) {
+                0 => HpvMsg::Join(Peer::arbitrary(g)),
+                1 => HpvMsg::ForwardJoin {
+                    joining: Peer::arbitrary(g),
+                    forwarder: Peer::arbitrary(g),
+                    ttl: g.gen::<u32>() as usize,
+                },
+                2 => HpvMsg::Neighbour {
+                    id: g.gen(),
+                    peer: Peer::arbitrary(g),
+                    prio: g.gen(),
+                },
+                3 => HpvMsg::NeighbourReply {
+                    id: g.gen(),
+                    peer: Peer::arbitrary(g),
+                    accepted: g.gen(),
+                },
+                4 => HpvMsg::Shuffle {
+                    id: g.gen(),
+                    origin: Peer::arbitrary(g),
+                    exchange: HashSet::arbitrary(g),
+                    ttl: g.gen::<u32>() as usize,
+                },
+                5 => HpvMsg::ShuffleReply(g.gen(), HashSet::arbitrary(g)),
+                6 => HpvMsg::Disconnect {
+                    peer: Peer::arbitrary(g),
+                    alive: g.gen(),
+                },
+                7 => HpvMsg::Heartbeat(Peer::arbitrary(g)),
+                8 => HpvMsg::Handoff(HashSet::arbitrary(g)),
+                9 => HpvMsg::JoinReply(Peer::arbitrary(g)),
+                10 => HpvMsg::Probe(Peer::arbitrary(g)),
+                11 => HpvMsg::ProbeReply(Peer::arbitrary(g)),
+                _ => HpvMsg::SizeSketch {
+                    epoch: g.gen(),
+                    hashes: Vec::arbitrary(g),
+                },
+            }
+        }
+    }
+
+    /// Peers only compare by id, so addresses are checked separately
+    fn addresses(msg: &HpvMsg) -> Vec<(PeerId, SocketAddr)> {
+        let mut peers: Vec<&Peer> = match msg {
+            HpvMsg::Join(p)
+            | HpvMsg::JoinReply(p)
+            | HpvMsg::Heartbeat(p)
+            | HpvMsg::Probe(p)
+            | HpvMsg::ProbeReply(p) => vec![p],
+            HpvMsg::ForwardJoin {
+                joining, forwarder, ..
+            } => vec![joining, forwarder],
+            HpvMsg::Neighbour { peer, .. }
+            | HpvMsg::NeighbourReply { peer, .. }
+            | HpvMsg::Disconnect { peer, .. } => vec![peer],
+            HpvMsg::Shuffle {
+                origin, exchange, ..
+            } => exchange.iter().chain(Some(origin)).collect(),
+            HpvMsg::ShuffleReply(_, exchange) | HpvMsg::Handoff(exchange) => {
+                exchange.iter().collect()
+            }
+            HpvMsg::SizeSketch { .. } => vec![],
+        };
+        peers.sort();
+        peers.iter().map(|p| (p.id, p.addr)).collect()
+    }
+
+    quickcheck! {
+        fn roundtrip(msg: HpvMsg) -> bool {
+            let decoded = decode(&encode(&msg).unwrap()).unwrap();
+            decoded == msg && addresses(&decoded) == addresses(&msg)
+        }
+
+        fn frame_roundtrip(msgs: Vec<HpvMsg>) -> bool {
+            let mut wire = Vec::new();
+            for msg in msgs.iter() {
+                write_frame(&mut wire, msg).unwrap();
+            }
+            let mut reader = &wire[..];
+            msgs.iter().all(|msg| read_frame(&mut reader).unwrap() == *msg) && reader.is_empty()
+        }
+
+        fn truncation_is_an_error(msg: HpvMsg) -> bool {
+            let encoded = encode(&msg).unwrap();
+            (0..encoded.len()).all(|len| match decode(&encoded[..len]) {
+                Err(DecodeError::Truncated) => true,
+                _ => false,
+            })
+        }
+
+        fn arbitrary_input_does_not_panic(bytes: Vec<u8>) -> bool {
+            let _ = decode(&bytes);
+            true
+        }
+    }
+
+    #[test]
+    fn reject_unknown_versions() {
+        let mut encoded = encode(&HpvMsg::Join(peer())).unwrap();
+        encoded[0] = PROTOCOL_VERSION + 1;
+        match decode(&encoded) {
+            Err(DecodeError::UnsupportedVersion(v)) => assert_eq!(v, PROTOCOL_VERSION + 1),
+            other => panic!("Expected unsupported version, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reject_trailing_bytes() {
+        let disconnect = HpvMsg::Disconnect {
+            peer: peer(),
+            alive: true,
+        };
+        let mut encoded = encode(&disconnect).unwrap();
+        encoded.push(0);
+        match decode(&encoded) {
+            Err(DecodeError::TrailingBytes(1)) => {}
+            other => panic!("Expected trailing bytes, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn refuse_frames_beyond_maximum() {
+        let exchange: HashSet<Peer> = (0..MAX_FRAME_LEN / 20).map(|_| peer()).collect();
+        let shuffle = HpvMsg::Shuffle {
+            id: 0,
+            origin: peer(),
+            exchange: exchange,
+            ttl: 1,
+        };
+        match encode(&shuffle) {
+            Err(EncodeError::FrameTooLarge(n)) => assert!(n > MAX_FRAME_LEN),
+            other => panic!("Expected frame too large, got {:?}", other),
+        }
+        let mut wire = Vec::new();
+        assert!(write_frame(&mut wire, &shuffle).is_err());
+        assert!(wire.is_empty());
+    }
+
+    #[test]
+    fn refuse_ttl_beyond_32_bits() {
+        let ttl = u32::max_value() as usize;
+        let forward_join = |ttl| HpvMsg::ForwardJoin {
+            joining: peer(),
+            forwarder: peer(),
+            ttl: ttl,
+        };
+        assert!(encode(&forward_join(ttl)).is_ok());
+        if let Some(ttl) = ttl.checked_add(1) {
+            match encode(&forward_join(ttl)) {
+                Err(EncodeError::TtlOverflow(t)) => assert_eq!(t, ttl),
+                other => panic!("Expected TTL overflow, got {:?}", other),
+            }
+        }
+    }
+}
@@ -1,6 +1,15 @@
+use super::config_file::{deserialize_addrs, deserialize_duration, deserialize_optional_duration};
+use bounded_set::EvictionPolicy;
+use std::error::Error;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::time::Duration;
 
-#[derive(Clone)]
+/// The parameters of `HyParView`. `Config::builder` checks that they make sense together, and
+/// `Config::load` reads them from a TOML file with the same keys.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default = "Config::default", deny_unknown_fields)]
 pub struct Config {
     pub max_active_view_size: usize,
     pub max_passive_view_size: usize,
@@ -9,7 +18,48 @@ pub struct Config {
     pub shuffle_rwl: usize,
     pub shuffle_active: usize,
     pub shuffle_passive: usize,
+    /// Picks the active peer that is dropped to make room for a new one
+    pub active_eviction: EvictionPolicy,
+    /// Picks the passive peer that is forgotten to make room for a new one, and the one probed next
+    pub passive_eviction: EvictionPolicy,
+    #[serde(deserialize_with = "deserialize_duration")]
     pub shuffle_interval: Duration,
+    /// How long a join waits for its acknowledgement before the next contact is tried
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub join_timeout: Duration,
+    /// Contacts to join through, in random order, at startup and whenever both views run empty
+    #[serde(deserialize_with = "deserialize_addrs")]
+    pub seed_addrs: Vec<SocketAddr>,
+    /// The delay before the seeds are tried again after none of them acknowledged our join. It
+    /// doubles with every failed round, up to `max_seed_backoff`.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub seed_backoff: Duration,
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub max_seed_backoff: Duration,
+    /// How long a shuffle request waits for its reply before the offer is forgotten
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub shuffle_timeout: Duration,
+    /// How long a neighbour request waits for its reply before another passive peer is asked
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub neighbour_timeout: Duration,
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub heartbeat_interval: Duration,
+    /// Consecutive heartbeat rounds an active peer may stay silent before it is considered failed
+    pub max_missed_heartbeats: usize,
+    /// How often the passive peer next in line for eviction is probed, dropping it if it does not
+    /// answer before the next probe; `None` disables probing
+    #[serde(deserialize_with = "deserialize_optional_duration")]
+    pub passive_probe_interval: Option<Duration>,
+    /// Resizes both views after the estimated size of the overlay; `None` keeps the sizes above
+    pub adaptive_sizing: Option<AdaptiveSizing>,
+    /// Where `HyParViewActor` keeps a snapshot of both views, to return to its former neighbours
+    /// after a restart rather than join through the seeds; `None` disables snapshots
+    pub snapshot_path: Option<PathBuf>,
+    /// How often the snapshot is saved
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub snapshot_interval: Duration,
+    /// Seeds every random protocol decision, so that a run can be replayed; `None` seeds from entropy
+    pub seed: Option<u64>,
 }
 
 impl Config {
@@ -22,7 +72,544 @@ impl Config {
             shuffle_rwl: 1,
             shuffle_active: 2,
             shuffle_passive: 2,
+            active_eviction: EvictionPolicy::Random,
+            passive_eviction: EvictionPolicy::LeastRecentlyUsed,
             shuffle_interval: Duration::from_secs(30),
+            join_timeout: Duration::from_secs(5),
+            seed_addrs: Vec::new(),
+            seed_backoff: Duration::from_secs(1),
+            max_seed_backoff: Duration::from_secs(60),
+            shuffle_timeout: Duration::from_secs(10),
+            neighbour_timeout: Duration::from_secs(5),
+            heartbeat_interval: Duration::from_secs(5),
+            max_missed_heartbeats: 3,
+            passive_probe_interval: None,
+            adaptive_sizing: None,
+            snapshot_path: None,
+            snapshot_interval: Duration::from_secs(60),
+            seed: None,
         }
     }
+
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder {
+            config: Config::default(),
+        }
+    }
+
+    /// Checks every constraint among the parameters, reporting all that are violated
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut violations = Vec::new();
+        if self.max_active_view_size == 0 {
+            violations.push(ConfigViolation::EmptyActiveView);
+        }
+        if self.max_passive_view_size == 0 {
+            violations.push(ConfigViolation::EmptyPassiveView);
+        }
+        if self.passive_rwl > self.active_rwl {
+            violations.push(ConfigViolation::PassiveWalkExceedsActiveWalk {
+                passive_rwl: self.passive_rwl,
+                active_rwl: self.active_rwl,
+            });
+        }
+        if self.shuffle_rwl == 0 {
+            violations.push(ConfigViolation::EmptyShuffleWalk);
+        }
+        if self.shuffle_active > self.max_active_view_size {
+            violations.push(ConfigViolation::ShuffleExceedsActiveView {
+                shuffle_active: self.shuffle_active,
+                max_active_view_size: self.max_active_view_size,
+            });
+        }
+        if self.shuffle_passive > self.max_passive_view_size {
+            violations.push(ConfigViolation::ShuffleExceedsPassiveView {
+                shuffle_passive: self.shuffle_passive,
+                max_passive_view_size: self.max_passive_view_size,
+            });
+        }
+        let durations = vec![
+            ("shuffle_interval", Some(self.shuffle_interval)),
+            ("join_timeout", Some(self.join_timeout)),
+            ("seed_backoff", Some(self.seed_backoff)),
+            ("shuffle_timeout", Some(self.shuffle_timeout)),
+            ("neighbour_timeout", Some(self.neighbour_timeout)),
+            ("heartbeat_interval", Some(self.heartbeat_interval)),
+            ("passive_probe_interval", self.passive_probe_interval),
+            ("snapshot_interval", Some(self.snapshot_interval)),
+        ];
+        for (name, duration) in durations {
+            if duration == Some(Duration::from_secs(0)) {
+                violations.push(ConfigViolation::ZeroDuration(name));
+            }
+        }
+        if self.seed_backoff > self.max_seed_backoff {
+            violations.push(ConfigViolation::SeedBackoffExceedsMaximum);
+        }
+        if self.max_missed_heartbeats == 0 {
+            violations.push(ConfigViolation::NoMissedHeartbeats);
+        }
+        if let Some(ref sizing) = self.adaptive_sizing {
+            if sizing.min_active_view_size == 0
+                || sizing.min_active_view_size > sizing.max_active_view_size
+            {
+                violations.push(ConfigViolation::InvalidAdaptiveActiveBounds);
+            }
+            if sizing.min_passive_view_size == 0
+                || sizing.min_passive_view_size > sizing.max_passive_view_size
+            {
+                violations.push(ConfigViolation::InvalidAdaptivePassiveBounds);
+            }
+            if sizing.min_active_view_size < self.shuffle_active {
+                violations.push(ConfigViolation::ShuffleExceedsAdaptiveActiveView {
+                    shuffle_active: self.shuffle_active,
+                    min_active_view_size: sizing.min_active_view_size,
+                });
+            }
+            if sizing.min_passive_view_size < self.shuffle_passive {
+                violations.push(ConfigViolation::ShuffleExceedsAdaptivePassiveView {
+                    shuffle_passive: self.shuffle_passive,
+                    min_passive_view_size: sizing.min_passive_view_size,
+                });
+            }
+            if sizing.sketch_size < 2 {
+                violations.push(ConfigViolation::SketchTooSmall(sizing.sketch_size));
+            }
+            if sizing.sketch_epoch_rounds == 0 {
+                violations.push(ConfigViolation::EmptySketchEpoch);
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError {
+                violations: violations,
+            })
+        }
+    }
+}
+
+/// Builds a `Config` from the defaults, validating it once it is complete
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    pub fn max_active_view_size(mut self, max_active_view_size: usize) -> ConfigBuilder {
+        self.config.max_active_view_size = max_active_view_size;
+        self
+    }
+
+    pub fn max_passive_view_size(mut self, max_passive_view_size: usize) -> ConfigBuilder {
+        self.config.max_passive_view_size = max_passive_view_size;
+        self
+    }
+
+    pub fn active_rwl(mut self, active_rwl: usize) -> ConfigBuilder {
+        self.config.active_rwl = active_rwl;
+        self
+    }
+
+    pub fn passive_rwl(mut self, passive_rwl: usize) -> ConfigBuilder {
+        self.config.passive_rwl = passive_rwl;
+        self
+    }
+
+    pub fn shuffle_rwl(mut self, shuffle_rwl: usize) -> ConfigBuilder {
+        self.config.shuffle_rwl = shuffle_rwl;
+        self
+    }
+
+    pub fn shuffle_active(mut self, shuffle_active: usize) -> ConfigBuilder {
+        self.config.shuffle_active = shuffle_active;
+        self
+    }
+
+    pub fn shuffle_passive(mut self, shuffle_passive: usize) -> ConfigBuilder {
+        self.config.shuffle_passive = shuffle_passive;
+        self
+    }
+
+    pub fn active_eviction(mut self, active_eviction: EvictionPolicy) -> ConfigBuilder {
+        self.config.active_eviction = active_eviction;
+        self
+    }
+
+    pub fn passive_eviction(mut self, passive_eviction: EvictionPolicy) -> ConfigBuilder {
+        self.config.passive_eviction = passive_eviction;
+        self
+    }
+
+    pub fn shuffle_interval(mut self, shuffle_interval: Duration) -> ConfigBuilder {
+        self.config.shuffle_interval = shuffle_interval;
+        self
+    }
+
+    pub fn join_timeout(mut self, join_timeout: Duration) -> ConfigBuilder {
+        self.config.join_timeout = join_timeout;
+        self
+    }
+
+    pub fn seed_addrs(mut self, seed_addrs: Vec<SocketAddr>) -> ConfigBuilder {
+        self.config.seed_addrs = seed_addrs;
+        self
+    }
+
+    pub fn seed_backoff(mut self, seed_backoff: Duration) -> ConfigBuilder {
+        self.config.seed_backoff = seed_backoff;
+        self
+    }
+
+    pub fn max_seed_backoff(mut self, max_seed_backoff: Duration) -> ConfigBuilder {
+        self.config.max_seed_backoff = max_seed_backoff;
+        self
+    }
+
+    pub fn shuffle_timeout(mut self, shuffle_timeout: Duration) -> ConfigBuilder {
+        self.config.shuffle_timeout = shuffle_timeout;
+        self
+    }
+
+    pub fn neighbour_timeout(mut self, neighbour_timeout: Duration) -> ConfigBuilder {
+        self.config.neighbour_timeout = neighbour_timeout;
+        self
+    }
+
+    pub fn heartbeat_interval(mut self, heartbeat_interval: Duration) -> ConfigBuilder {
+        self.config.heartbeat_interval = heartbeat_interval;
+        self
+    }
+
+    pub fn max_missed_heartbeats(mut self, max_missed_heartbeats: usize) -> ConfigBuilder {
+        self.config.max_missed_heartbeats = max_missed_heartbeats;
+        self
+    }
+
+    pub fn passive_probe_interval(mut self, passive_probe_interval: Option<Duration>) -> ConfigBuilder {
+        self.config.passive_probe_interval = passive_probe_interval;
+        self
+    }
+
+    pub fn adaptive_sizing(mut self, adaptive_sizing: Option<AdaptiveSizing>) -> ConfigBuilder {
+        self.config.adaptive_sizing = adaptive_sizing;
+        self
+    }
+
+    pub fn snapshot_path(mut self, snapshot_path: Option<PathBuf>) -> ConfigBuilder {
+        self.config.snapshot_path = snapshot_path;
+        self
+    }
+
+    pub fn snapshot_interval(mut self, snapshot_interval: Duration) -> ConfigBuilder {
+        self.config.snapshot_interval = snapshot_interval;
+        self
+    }
+
+    pub fn seed(mut self, seed: Option<u64>) -> ConfigBuilder {
+        self.config.seed = seed;
+        self
+    }
+
+    pub fn build(self) -> Result<Config, ConfigError> {
+        self.config.validate().map(|_| self.config)
+    }
+}
+
+/// A constraint that a `Config` does not satisfy
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum ConfigViolation {
+    EmptyActiveView,
+    EmptyPassiveView,
+    /// Joining peers would enter passive views only after the walk had ended
+    PassiveWalkExceedsActiveWalk {
+        passive_rwl: usize,
+        active_rwl: usize,
+    },
+    EmptyShuffleWalk,
+    ShuffleExceedsActiveView {
+        shuffle_active: usize,
+        max_active_view_size: usize,
+    },
+    ShuffleExceedsPassiveView {
+        shuffle_passive: usize,
+        max_passive_view_size: usize,
+    },
+    /// The named timeout or interval is zero
+    ZeroDuration(&'static str),
+    SeedBackoffExceedsMaximum,
+    /// Active peers would be considered failed at their first heartbeat round
+    NoMissedHeartbeats,
+    InvalidAdaptiveActiveBounds,
+    InvalidAdaptivePassiveBounds,
+    /// Adaptive sizing could shrink the active view below what a shuffle takes from it
+    ShuffleExceedsAdaptiveActiveView {
+        shuffle_active: usize,
+        min_active_view_size: usize,
+    },
+    /// Adaptive sizing could shrink the passive view below what a shuffle takes from it
+    ShuffleExceedsAdaptivePassiveView {
+        shuffle_passive: usize,
+        min_passive_view_size: usize,
+    },
+    SketchTooSmall(usize),
+    EmptySketchEpoch,
+}
+
+impl fmt::Display for ConfigViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigViolation::EmptyActiveView => write!(f, "max_active_view_size must be positive"),
+            ConfigViolation::EmptyPassiveView => {
+                write!(f, "max_passive_view_size must be positive")
+            }
+            ConfigViolation::PassiveWalkExceedsActiveWalk {
+                passive_rwl,
+                active_rwl,
+            } => write!(
+                f,
+                "passive_rwl ({}) must not exceed active_rwl ({})",
+                passive_rwl, active_rwl
+            ),
+            ConfigViolation::EmptyShuffleWalk => write!(f, "shuffle_rwl must be positive"),
+            ConfigViolation::ShuffleExceedsActiveView {
+                shuffle_active,
+                max_active_view_size,
+            } => write!(
+                f,
+                "shuffle_active ({}) must not exceed max_active_view_size ({})",
+                shuffle_active, max_active_view_size
+            ),
+            ConfigViolation::ShuffleExceedsPassiveView {
+                shuffle_passive,
+                max_passive_view_size,
+            } => write!(
+                f,
+                "shuffle_passive ({}) must not exceed max_passive_view_size ({})",
+                shuffle_passive, max_passive_view_size
+            ),
+            ConfigViolation::ZeroDuration(name) => write!(f, "{} must be positive", name),
+            ConfigViolation::SeedBackoffExceedsMaximum => {
+                write!(f, "seed_backoff must not exceed max_seed_backoff")
+            }
+            ConfigViolation::NoMissedHeartbeats => {
+                write!(f, "max_missed_heartbeats must be positive")
+            }
+            ConfigViolation::InvalidAdaptiveActiveBounds => write!(
+                f,
+                "adaptive active view sizes must be positive, with the minimum not above the maximum"
+            ),
+            ConfigViolation::InvalidAdaptivePassiveBounds => write!(
+                f,
+                "adaptive passive view sizes must be positive, with the minimum not above the maximum"
+            ),
+            ConfigViolation::ShuffleExceedsAdaptiveActiveView {
+                shuffle_active,
+                min_active_view_size,
+            } => write!(
+                f,
+                "shuffle_active ({}) must not exceed adaptive_sizing.min_active_view_size ({})",
+                shuffle_active, min_active_view_size
+            ),
+            ConfigViolation::ShuffleExceedsAdaptivePassiveView {
+                shuffle_passive,
+                min_passive_view_size,
+            } => write!(
+                f,
+                "shuffle_passive ({}) must not exceed adaptive_sizing.min_passive_view_size ({})",
+                shuffle_passive, min_passive_view_size
+            ),
+            ConfigViolation::SketchTooSmall(size) => {
+                write!(f, "sketch_size ({}) must be at least 2", size)
+            }
+            ConfigViolation::EmptySketchEpoch => write!(f, "sketch_epoch_rounds must be positive"),
+        }
+    }
+}
+
+/// Every constraint that a rejected `Config` violates
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct ConfigError {
+    pub violations: Vec<ConfigViolation>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid configuration: ")?;
+        for (i, v) in self.violations.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", v)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ConfigError {}
+
+/// Sizes the active view as `log10(n) + c` and the passive view as `k * (log10(n) + c)`, as the
+/// HyParView paper proposes, where `n` is the estimated number of peers in the overlay
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default = "AdaptiveSizing::default", deny_unknown_fields)]
+pub struct AdaptiveSizing {
+    /// The `c` above
+    pub active_constant: usize,
+    /// The `k` above
+    pub passive_factor: usize,
+    pub min_active_view_size: usize,
+    pub max_active_view_size: usize,
+    pub min_passive_view_size: usize,
+    pub max_passive_view_size: usize,
+    /// The number of hashes gossiped to estimate the size of the overlay; more give a closer estimate
+    pub sketch_size: usize,
+    /// The shuffle rounds after which a new sketch is started, which bounds how long peers that left
+    /// are still counted. Sketches must spread through the overlay within this many rounds.
+    pub sketch_epoch_rounds: u32,
+}
+
+impl AdaptiveSizing {
+    pub fn default() -> AdaptiveSizing {
+        AdaptiveSizing {
+            active_constant: 1,
+            passive_factor: 6,
+            min_active_view_size: 2,
+            max_active_view_size: 10,
+            min_passive_view_size: 6,
+            max_passive_view_size: 60,
+            sketch_size: 64,
+            sketch_epoch_rounds: 20,
+        }
+    }
+
+    /// The sizes of the active and passive view for an overlay of `n` peers
+    pub fn view_sizes(&self, n: usize) -> (usize, usize) {
+        let active = (n.max(1) as f64).log10().round() as usize + self.active_constant;
+        (
+            active
+                .max(self.min_active_view_size)
+                .min(self.max_active_view_size),
+            (self.passive_factor * active)
+                .max(self.min_passive_view_size)
+                .min(self.max_passive_view_size),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hpv::{HyParView, Peer, PeerId};
+
+    #[test]
+    fn default_is_valid() {
+        assert!(Config::default().validate().is_ok());
+        assert!(
+            Config::builder()
+                .adaptive_sizing(Some(AdaptiveSizing::default()))
+                .build()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn build_with_overrides() {
+        let config = Config::builder()
+            .max_active_view_size(5)
+            .shuffle_interval(Duration::from_secs(10))
+            .build()
+            .unwrap();
+        assert_eq!(config.max_active_view_size, 5);
+        assert_eq!(config.shuffle_interval, Duration::from_secs(10));
+        assert_eq!(config.passive_rwl, Config::default().passive_rwl);
+    }
+
+    #[test]
+    fn report_every_violation() {
+        let result = Config::builder()
+            .max_active_view_size(1)
+            .passive_rwl(4)
+            .shuffle_rwl(0)
+            .heartbeat_interval(Duration::from_secs(0))
+            .build();
+        assert_eq!(
+            result.unwrap_err().violations,
+            vec![
+                ConfigViolation::PassiveWalkExceedsActiveWalk {
+                    passive_rwl: 4,
+                    active_rwl: 3,
+                },
+                ConfigViolation::EmptyShuffleWalk,
+                ConfigViolation::ShuffleExceedsActiveView {
+                    shuffle_active: 2,
+                    max_active_view_size: 1,
+                },
+                ConfigViolation::ZeroDuration("heartbeat_interval"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_empty_views() {
+        let result = Config::builder()
+            .max_active_view_size(0)
+            .max_passive_view_size(0)
+            .shuffle_active(0)
+            .shuffle_passive(0)
+            .build();
+        assert_eq!(
+            result.unwrap_err().violations,
+            vec![
+                ConfigViolation::EmptyActiveView,
+                ConfigViolation::EmptyPassiveView,
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_adaptive_bounds_below_shuffle_sizes() {
+        let mut sizing = AdaptiveSizing::default();
+        sizing.min_active_view_size = 1;
+        sizing.min_passive_view_size = 2;
+        let result = Config::builder()
+            .shuffle_passive(3)
+            .adaptive_sizing(Some(sizing))
+            .build();
+        assert_eq!(
+            result.unwrap_err().violations,
+            vec![
+                ConfigViolation::ShuffleExceedsAdaptiveActiveView {
+                    shuffle_active: 2,
+                    min_active_view_size: 1,
+                },
+                ConfigViolation::ShuffleExceedsAdaptivePassiveView {
+                    shuffle_passive: 3,
+                    min_passive_view_size: 2,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn refuse_to_create_with_invalid_config() {
+        let peer = Peer::new(PeerId::random(), "127.0.0.1:4000".parse().unwrap());
+        let mut invalid = Config::default();
+        invalid.max_active_view_size = 0;
+
+        let error = HyParView::new(peer, invalid).err().unwrap();
+        assert!(error.violations.contains(&ConfigViolation::EmptyActiveView));
+    }
+
+    #[test]
+    fn keep_config_when_rejecting_another() {
+        let peer = Peer::new(PeerId::random(), "127.0.0.1:4000".parse().unwrap());
+        let mut hpv = HyParView::new(peer, Config::default()).unwrap();
+        let mut invalid = Config::default();
+        invalid.max_passive_view_size = 0;
+
+        assert!(hpv.set_config(invalid).is_err());
+        assert_eq!(
+            hpv.config().max_passive_view_size,
+            Config::default().max_passive_view_size
+        );
+    }
 }
@@ -0,0 +1,500 @@
+extern crate humantime;
+extern crate serde;
+extern crate toml;
+
+use self::serde::de::{Deserialize, Deserializer, Error as DeError};
+use self::toml::value::{Table, Value};
+use super::{Config, ConfigError};
+use std::env;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+/// Starts the names of the environment variables that override configuration parameters. Others
+/// that start with it are ignored with a warning.
+pub const ENV_PREFIX: &str = "HYPARVIEW_";
+
+// The parameters that environment variables override, apart from the tables among them
+const PARAMETERS: &[&str] = &[
+    "max_active_view_size",
+    "max_passive_view_size",
+    "active_rwl",
+    "passive_rwl",
+    "shuffle_rwl",
+    "shuffle_active",
+    "shuffle_passive",
+    "active_eviction",
+    "passive_eviction",
+    "shuffle_interval",
+    "join_timeout",
+    "seed_addrs",
+    "seed_backoff",
+    "max_seed_backoff",
+    "shuffle_timeout",
+    "neighbour_timeout",
+    "heartbeat_interval",
+    "max_missed_heartbeats",
+    "passive_probe_interval",
+    "snapshot_path",
+    "snapshot_interval",
+    "seed",
+];
+
+const ADAPTIVE_SIZING_PARAMETERS: &[&str] = &[
+    "active_constant",
+    "passive_factor",
+    "min_active_view_size",
+    "max_active_view_size",
+    "min_passive_view_size",
+    "max_passive_view_size",
+    "sketch_size",
+    "sketch_epoch_rounds",
+];
+
+impl Config {
+    /// Reads the TOML configuration at `path`, overridden by the `HYPARVIEW_*` environment variables
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, ConfigLoadError> {
+        let toml = fs::read_to_string(path).map_err(ConfigLoadError::Io)?;
+        Config::from_toml_and_env(&toml, unicode_vars(env::vars_os()))
+    }
+
+    /// Parses a TOML configuration. Parameters it leaves out keep their default.
+    pub fn from_toml(toml: &str) -> Result<Config, ConfigLoadError> {
+        Config::from_toml_and_env(toml, Vec::new())
+    }
+
+    /// Parses a TOML configuration, overriding its parameters with the `HYPARVIEW_*` variables
+    /// among `vars`. `HYPARVIEW_SHUFFLE_INTERVAL=45s` sets `shuffle_interval`, and a double
+    /// underscore reaches into a table, as in `HYPARVIEW_ADAPTIVE_SIZING__SKETCH_SIZE=128`.
+    /// Variables that name no parameter are ignored with a warning, as the prefix is not ours alone.
+    pub fn from_toml_and_env<I>(toml: &str, vars: I) -> Result<Config, ConfigLoadError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut table: Table = toml::from_str(toml).map_err(ConfigLoadError::Parse)?;
+        for (name, value) in vars {
+            if name.starts_with(ENV_PREFIX) {
+                let key = name[ENV_PREFIX.len()..].to_lowercase();
+                if is_parameter(&key) {
+                    override_key(&mut table, &key, parse_env_value(value));
+                } else {
+                    println!("[WARN] Ignoring {}, which names no configuration parameter", name);
+                }
+            }
+        }
+
+        let config: Config = Value::Table(table)
+            .try_into()
+            .map_err(ConfigLoadError::Parse)?;
+        config.validate().map_err(ConfigLoadError::Invalid)?;
+        Ok(config)
+    }
+}
+
+// Variables that are not valid Unicode cannot hold a parameter, but are worth a warning if they
+// look like they were meant to
+fn unicode_vars<I>(vars: I) -> Vec<(String, String)>
+where
+    I: IntoIterator<Item = (OsString, OsString)>,
+{
+    vars.into_iter()
+        .filter_map(|(name, value)| match (name.into_string(), value.into_string()) {
+            (Ok(name), Ok(value)) => Some((name, value)),
+            (name, _) => {
+                let name = name.unwrap_or_else(|name| name.to_string_lossy().into_owned());
+                if name.starts_with(ENV_PREFIX) {
+                    println!("[WARN] Ignoring {}, which is not valid Unicode", name);
+                }
+                None
+            }
+        })
+        .collect()
+}
+
+fn is_parameter(key: &str) -> bool {
+    match key.find("__") {
+        Some(split) => {
+            &key[..split] == "adaptive_sizing"
+                && ADAPTIVE_SIZING_PARAMETERS.contains(&&key[split + 2..])
+        }
+        None => PARAMETERS.contains(&key),
+    }
+}
+
+// Sets the value at a key path like `adaptive_sizing__sketch_size`, creating tables along the way
+fn override_key(table: &mut Table, key: &str, value: Value) {
+    match key.find("__") {
+        Some(split) => {
+            let nested = table
+                .entry(key[..split].to_string())
+                .or_insert_with(|| Value::Table(Table::new()));
+            if !nested.is_table() {
+                *nested = Value::Table(Table::new());
+            }
+            if let Value::Table(ref mut nested) = *nested {
+                override_key(nested, &key[split + 2..], value);
+            }
+        }
+        None => {
+            table.insert(key.to_string(), value);
+        }
+    }
+}
+
+// Reads numbers, booleans and arrays as TOML, and anything else as a bare string
+fn parse_env_value(value: String) -> Value {
+    toml::from_str::<Table>(&format!("v = {}", value))
+        .ok()
+        .and_then(|mut parsed| parsed.remove("v"))
+        .unwrap_or(Value::String(value))
+}
+
+/// Reads a duration written like `30s`, `1m 30s` or `500ms`
+pub(super) fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let text = String::deserialize(deserializer)?;
+    humantime::parse_duration(&text)
+        .map_err(|e| D::Error::custom(format!("invalid duration {:?}: {}", text, e)))
+}
+
+pub(super) fn deserialize_optional_duration<'de, D>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_duration(deserializer).map(Some)
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Addrs {
+    List(Vec<String>),
+    Joined(String),
+}
+
+/// Reads socket addresses from an array, or from a single comma-separated string as an environment
+/// variable would hold them
+pub(super) fn deserialize_addrs<'de, D>(deserializer: D) -> Result<Vec<SocketAddr>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let addrs = match Addrs::deserialize(deserializer)? {
+        Addrs::List(addrs) => addrs,
+        Addrs::Joined(joined) => joined
+            .split(',')
+            .map(|addr| addr.trim().to_string())
+            .filter(|addr| !addr.is_empty())
+            .collect(),
+    };
+    addrs
+        .iter()
+        .map(|addr| {
+            addr.parse()
+                .map_err(|_| D::Error::custom(format!("invalid socket address {:?}", addr)))
+        })
+        .collect()
+}
+
+/// Why a configuration could not be loaded
+#[derive(Debug)]
+pub enum ConfigLoadError {
+    /// The configuration file could not be read
+    Io(io::Error),
+    /// The configuration, with its overrides, is not TOML describing a `Config`
+    Parse(toml::de::Error),
+    /// The configuration was read, but its parameters do not make sense together
+    Invalid(ConfigError),
+}
+
+impl fmt::Display for ConfigLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigLoadError::Io(e) => write!(f, "Could not read configuration: {}", e),
+            ConfigLoadError::Parse(e) => write!(f, "Could not parse configuration: {}", e),
+            ConfigLoadError::Invalid(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for ConfigLoadError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bounded_set::EvictionPolicy;
+    use hpv::{AdaptiveSizing, ConfigViolation};
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn keep_defaults_for_missing_parameters() {
+        let config = Config::from_toml("").unwrap();
+        assert_eq!(config.shuffle_interval, Config::default().shuffle_interval);
+        assert_eq!(config.max_active_view_size, Config::default().max_active_view_size);
+        assert!(config.adaptive_sizing.is_none());
+    }
+
+    #[test]
+    fn parse_file() {
+        let config = Config::from_toml(
+            r#"
+            max_active_view_size = 5
+            max_passive_view_size = 30
+            active_rwl = 6
+            shuffle_interval = "1m 30s"
+            join_timeout = "500ms"
+            passive_probe_interval = "10s"
+            passive_eviction = "score_based"
+            seed_addrs = ["10.0.0.1:7000", "10.0.0.2:7000"]
+
+            [adaptive_sizing]
+            sketch_size = 128
+            "#,
+        ).unwrap();
+
+        assert_eq!(config.max_active_view_size, 5);
+        assert_eq!(config.max_passive_view_size, 30);
+        assert_eq!(config.active_rwl, 6);
+        assert_eq!(config.shuffle_interval, Duration::from_secs(90));
+        assert_eq!(config.join_timeout, Duration::from_millis(500));
+        assert_eq!(config.passive_probe_interval, Some(Duration::from_secs(10)));
+        assert_eq!(config.passive_eviction, EvictionPolicy::ScoreBased);
+        assert_eq!(
+            config.seed_addrs,
+            vec![
+                "10.0.0.1:7000".parse().unwrap(),
+                "10.0.0.2:7000".parse().unwrap(),
+            ]
+        );
+        let sizing = config.adaptive_sizing.unwrap();
+        assert_eq!(sizing.sketch_size, 128);
+        assert_eq!(sizing.passive_factor, 6);
+    }
+
+    #[test]
+    fn override_with_environment() {
+        let config = Config::from_toml_and_env(
+            "max_active_view_size = 5\nshuffle_interval = \"1m\"",
+            env(&[
+                ("HYPARVIEW_SHUFFLE_INTERVAL", "45s"),
+                ("HYPARVIEW_ACTIVE_RWL", "4"),
+                ("HYPARVIEW_SEED_ADDRS", "10.0.0.1:7000, 10.0.0.2:7000"),
+                ("HYPARVIEW_ADAPTIVE_SIZING__SKETCH_SIZE", "32"),
+                ("HOME", "/root"),
+            ]),
+        ).unwrap();
+
+        assert_eq!(config.max_active_view_size, 5);
+        assert_eq!(config.shuffle_interval, Duration::from_secs(45));
+        assert_eq!(config.active_rwl, 4);
+        assert_eq!(config.seed_addrs.len(), 2);
+        assert_eq!(config.adaptive_sizing.unwrap().sketch_size, 32);
+    }
+
+    #[test]
+    fn reject_unknown_parameters() {
+        match Config::from_toml("shuffle_intervall = \"30s\"") {
+            Err(ConfigLoadError::Parse(_)) => {}
+            other => panic!("Accepted a misspelt parameter: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn ignore_variables_that_name_no_parameter() {
+        let config = Config::from_toml_and_env(
+            "",
+            env(&[
+                ("HYPARVIEW_CONFIG", "/etc/hyparview.toml"),
+                ("HYPARVIEW_ACTIVE_RWLL", "4"),
+                ("HYPARVIEW_ADAPTIVE_SIZING__SKETCH", "32"),
+                ("HYPARVIEW_SEED_ADDRS__PORT", "7000"),
+            ]),
+        ).unwrap();
+
+        assert_eq!(config.active_rwl, Config::default().active_rwl);
+        assert!(config.adaptive_sizing.is_none());
+        assert!(config.seed_addrs.is_empty());
+    }
+
+    // A valid value other than the default for every parameter that variables may override
+    fn override_value(key: &str) -> &'static str {
+        match key {
+            "max_active_view_size" => "5",
+            "max_passive_view_size" => "30",
+            "active_rwl" => "6",
+            "passive_rwl" => "1",
+            "shuffle_rwl" => "2",
+            "shuffle_active" => "3",
+            "shuffle_passive" => "4",
+            "active_eviction" => "oldest_first",
+            "passive_eviction" => "score_based",
+            "shuffle_interval" => "45s",
+            "join_timeout" => "6s",
+            "seed_addrs" => "10.0.0.1:7000",
+            "seed_backoff" => "2s",
+            "max_seed_backoff" => "2m",
+            "shuffle_timeout" => "11s",
+            "neighbour_timeout" => "6s",
+            "heartbeat_interval" => "6s",
+            "max_missed_heartbeats" => "4",
+            "passive_probe_interval" => "10s",
+            "snapshot_path" => "/var/lib/hyparview/views.toml",
+            "snapshot_interval" => "2m",
+            "seed" => "7",
+            "adaptive_sizing__active_constant" => "2",
+            "adaptive_sizing__passive_factor" => "5",
+            "adaptive_sizing__min_active_view_size" => "3",
+            "adaptive_sizing__max_active_view_size" => "9",
+            "adaptive_sizing__min_passive_view_size" => "7",
+            "adaptive_sizing__max_passive_view_size" => "50",
+            "adaptive_sizing__sketch_size" => "32",
+            "adaptive_sizing__sketch_epoch_rounds" => "10",
+            _ => panic!("No value to override {} with", key),
+        }
+    }
+
+    #[test]
+    fn override_every_parameter() {
+        let keys = PARAMETERS.iter().map(|name| name.to_string()).chain(
+            ADAPTIVE_SIZING_PARAMETERS
+                .iter()
+                .map(|name| format!("adaptive_sizing__{}", name)),
+        );
+        let vars: Vec<(String, String)> = keys.map(|key| {
+            let name = format!("{}{}", ENV_PREFIX, key.to_uppercase());
+            (name, override_value(&key).to_string())
+        }).collect();
+
+        let config = Config::from_toml_and_env("", vars).unwrap();
+
+        // Every field is named, so that a new parameter does not compile without a check here
+        let default = Config::default();
+        let Config {
+            max_active_view_size,
+            max_passive_view_size,
+            active_rwl,
+            passive_rwl,
+            shuffle_rwl,
+            shuffle_active,
+            shuffle_passive,
+            active_eviction,
+            passive_eviction,
+            shuffle_interval,
+            join_timeout,
+            seed_addrs,
+            seed_backoff,
+            max_seed_backoff,
+            shuffle_timeout,
+            neighbour_timeout,
+            heartbeat_interval,
+            max_missed_heartbeats,
+            passive_probe_interval,
+            adaptive_sizing,
+            snapshot_path,
+            snapshot_interval,
+            seed,
+        } = config;
+        assert_ne!(max_active_view_size, default.max_active_view_size);
+        assert_ne!(max_passive_view_size, default.max_passive_view_size);
+        assert_ne!(active_rwl, default.active_rwl);
+        assert_ne!(passive_rwl, default.passive_rwl);
+        assert_ne!(shuffle_rwl, default.shuffle_rwl);
+        assert_ne!(shuffle_active, default.shuffle_active);
+        assert_ne!(shuffle_passive, default.shuffle_passive);
+        assert_ne!(active_eviction, default.active_eviction);
+        assert_ne!(passive_eviction, default.passive_eviction);
+        assert_ne!(shuffle_interval, default.shuffle_interval);
+        assert_ne!(join_timeout, default.join_timeout);
+        assert_ne!(seed_addrs, default.seed_addrs);
+        assert_ne!(seed_backoff, default.seed_backoff);
+        assert_ne!(max_seed_backoff, default.max_seed_backoff);
+        assert_ne!(shuffle_timeout, default.shuffle_timeout);
+        assert_ne!(neighbour_timeout, default.neighbour_timeout);
+        assert_ne!(heartbeat_interval, default.heartbeat_interval);
+        assert_ne!(max_missed_heartbeats, default.max_missed_heartbeats);
+        assert_ne!(passive_probe_interval, default.passive_probe_interval);
+        assert_ne!(snapshot_path, default.snapshot_path);
+        assert_ne!(snapshot_interval, default.snapshot_interval);
+        assert_ne!(seed, default.seed);
+
+        let default = AdaptiveSizing::default();
+        let AdaptiveSizing {
+            active_constant,
+            passive_factor,
+            min_active_view_size,
+            max_active_view_size,
+            min_passive_view_size,
+            max_passive_view_size,
+            sketch_size,
+            sketch_epoch_rounds,
+        } = adaptive_sizing.expect("Did not override adaptive sizing");
+        assert_ne!(active_constant, default.active_constant);
+        assert_ne!(passive_factor, default.passive_factor);
+        assert_ne!(min_active_view_size, default.min_active_view_size);
+        assert_ne!(max_active_view_size, default.max_active_view_size);
+        assert_ne!(min_passive_view_size, default.min_passive_view_size);
+        assert_ne!(max_passive_view_size, default.max_passive_view_size);
+        assert_ne!(sketch_size, default.sketch_size);
+        assert_ne!(sketch_epoch_rounds, default.sketch_epoch_rounds);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn skip_variables_that_are_not_unicode() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let invalid = OsString::from_vec(vec![0xff]);
+        let vars = unicode_vars(vec![
+            (OsString::from("HYPARVIEW_ACTIVE_RWL"), invalid.clone()),
+            (OsString::from("HYPARVIEW_SHUFFLE_RWL"), OsString::from("2")),
+            (invalid, OsString::from("4")),
+        ]);
+
+        assert_eq!(vars, env(&[("HYPARVIEW_SHUFFLE_RWL", "2")]));
+    }
+
+    #[test]
+    fn reject_malformed_values() {
+        for toml in &[
+            "shuffle_interval = \"30 parsecs\"",
+            "shuffle_interval = 30",
+            "seed_addrs = [\"localhost\"]",
+            "active_eviction = \"most_recently_used\"",
+        ] {
+            match Config::from_toml(toml) {
+                Err(ConfigLoadError::Parse(_)) => {}
+                other => panic!("Accepted {}: {:?}", toml, other),
+            }
+        }
+    }
+
+    #[test]
+    fn reject_invalid_config() {
+        match Config::from_toml("max_active_view_size = 0\nshuffle_interval = \"0s\"") {
+            Err(ConfigLoadError::Invalid(e)) => assert_eq!(
+                e.violations,
+                vec![
+                    ConfigViolation::EmptyActiveView,
+                    ConfigViolation::ShuffleExceedsActiveView {
+                        shuffle_active: 2,
+                        max_active_view_size: 0,
+                    },
+                    ConfigViolation::ZeroDuration("shuffle_interval"),
+                ]
+            ),
+            other => panic!("Accepted an invalid configuration: {:?}", other),
+        }
+    }
+}
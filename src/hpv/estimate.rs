@@ -0,0 +1,232 @@
+use super::PeerId;
+use std::collections::BTreeSet;
+use std::mem;
+
+/// Estimates the number of distinct peers from the `k` smallest hashes of their ids, a KMV sketch.
+/// Merging the sketches of two peers yields the sketch of all peers either of them saw, so
+/// gossiping sketches lets every peer estimate the size of the whole overlay.
+///
+/// A sketch can only grow, so sketches are kept per epoch, and the estimate counts the current
+/// and the previous epoch. Peers that left stop adding themselves, and drop out of the estimate
+/// once two newer epochs have started.
+#[derive(Clone, Debug)]
+pub struct SizeEstimate {
+    k: usize,
+    epoch: u64,
+    // the rounds since the current epoch started
+    rounds: u32,
+    current: BTreeSet<u64>,
+    previous: BTreeSet<u64>,
+}
+
+impl SizeEstimate {
+    /// Creates an empty sketch of `k` hashes, at least 2. The relative error of the estimate is
+    /// about `1 / sqrt(k - 2)`.
+    pub fn new(k: usize) -> SizeEstimate {
+        SizeEstimate {
+            k: k.max(2),
+            epoch: 0,
+            rounds: 0,
+            current: BTreeSet::new(),
+            previous: BTreeSet::new(),
+        }
+    }
+
+    pub fn set_k(&mut self, k: usize) {
+        self.k = k.max(2);
+        self.truncate();
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Counts a round, starting the next epoch after `epoch_rounds` rounds in the current one
+    pub fn tick(&mut self, epoch_rounds: u32) {
+        self.rounds += 1;
+        if self.rounds >= epoch_rounds {
+            self.next_epoch();
+        }
+    }
+
+    /// Adds a peer to the sketch of the current epoch
+    pub fn observe(&mut self, id: &PeerId) {
+        self.current.insert(hash_id(id));
+        self.truncate();
+    }
+
+    /// Adds the hashes of another sketch of `epoch`. A newer epoch starts here as well, and a sketch
+    /// older than the previous epoch is ignored. Epochs start one at a time, so a sketch from further
+    /// ahead only moves us on to the next epoch, without its hashes; otherwise a single peer could
+    /// skip every other peer far ahead, discarding all sketches.
+    pub fn merge(&mut self, epoch: u64, hashes: &[u64]) {
+        if epoch > self.epoch {
+            self.next_epoch();
+        }
+        if epoch == self.epoch {
+            self.current.extend(hashes.iter().cloned());
+        } else if epoch.checked_add(1) == Some(self.epoch) {
+            self.previous.extend(hashes.iter().cloned());
+        }
+        self.truncate();
+    }
+
+    /// The hashes of the current epoch
+    pub fn hashes(&self) -> Vec<u64> {
+        self.current.iter().cloned().collect()
+    }
+
+    /// The estimated number of distinct peers in the current and previous epoch, which is exact as
+    /// long as fewer than `k` were seen
+    pub fn estimate(&self) -> usize {
+        let mins: Vec<u64> = self.current
+            .union(&self.previous)
+            .take(self.k)
+            .cloned()
+            .collect();
+        match mins.last() {
+            Some(&kth) if mins.len() == self.k => {
+                // The k-th smallest of n uniform hashes lies around k/n of the way into the hash space
+                let fraction = (kth as f64 + 1.0) / (u64::max_value() as f64 + 1.0);
+                ((self.k - 1) as f64 / fraction).round() as usize
+            }
+            _ => mins.len(),
+        }
+    }
+
+    // The current sketch becomes the previous one
+    fn next_epoch(&mut self) {
+        self.previous = mem::replace(&mut self.current, BTreeSet::new());
+        self.epoch = self.epoch.saturating_add(1);
+        self.rounds = 0;
+    }
+
+    fn truncate(&mut self) {
+        truncate_to(&mut self.current, self.k);
+        truncate_to(&mut self.previous, self.k);
+    }
+}
+
+fn truncate_to(mins: &mut BTreeSet<u64>, k: usize) {
+    while mins.len() > k {
+        let max = *mins.iter().next_back().unwrap();
+        mins.remove(&max);
+    }
+}
+
+/// Hashes the first 8 bytes of the id with the SplitMix64 finalizer. Every peer must agree on the
+/// hash for sketches to merge, which rules out the randomly keyed hashers of the standard library.
+fn hash_id(id: &PeerId) -> u64 {
+    let mut z = id.as_bytes()[..8]
+        .iter()
+        .fold(0u64, |acc, b| acc << 8 | *b as u64);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod test {
+    extern crate rand;
+
+    use self::rand::rngs::SmallRng;
+    use self::rand::SeedableRng;
+    use super::*;
+
+    #[test]
+    fn count_exactly_below_k() {
+        let mut rng = SmallRng::from_seed([5; 16]);
+        let mut estimate = SizeEstimate::new(16);
+        let ids: Vec<PeerId> = (0..10).map(|_| PeerId::random_from(&mut rng)).collect();
+        ids.iter().chain(ids.iter()).for_each(|id| estimate.observe(id));
+
+        assert_eq!(estimate.estimate(), 10);
+    }
+
+    #[test]
+    fn estimate_large_counts() {
+        let mut rng = SmallRng::from_seed([5; 16]);
+        let mut estimate = SizeEstimate::new(256);
+        (0..100_000).for_each(|_| estimate.observe(&PeerId::random_from(&mut rng)));
+
+        let n = estimate.estimate();
+        assert!(n > 80_000 && n < 120_000, "estimated {}", n);
+    }
+
+    #[test]
+    fn merge_counts_union() {
+        let mut rng = SmallRng::from_seed([5; 16]);
+        let ids: Vec<PeerId> = (0..10_000).map(|_| PeerId::random_from(&mut rng)).collect();
+        let (mut left, mut right, mut all) = (
+            SizeEstimate::new(64),
+            SizeEstimate::new(64),
+            SizeEstimate::new(64),
+        );
+        ids[..6_000].iter().for_each(|id| left.observe(id));
+        ids[4_000..].iter().for_each(|id| right.observe(id));
+        ids.iter().for_each(|id| all.observe(id));
+
+        left.merge(0, &right.hashes());
+        assert_eq!(left.hashes(), all.hashes());
+    }
+
+    #[test]
+    fn forget_departed_peers_after_two_epochs() {
+        let mut rng = SmallRng::from_seed([5; 16]);
+        let ids: Vec<PeerId> = (0..10).map(|_| PeerId::random_from(&mut rng)).collect();
+        let mut estimate = SizeEstimate::new(16);
+        ids.iter().for_each(|id| estimate.observe(id));
+
+        // Only half of the peers remain to add themselves in later epochs
+        estimate.tick(1);
+        ids[..5].iter().for_each(|id| estimate.observe(id));
+        assert_eq!(estimate.estimate(), 10);
+        estimate.tick(1);
+        ids[..5].iter().for_each(|id| estimate.observe(id));
+        assert_eq!(estimate.estimate(), 5);
+    }
+
+    #[test]
+    fn follow_newer_epochs_one_at_a_time() {
+        let mut rng = SmallRng::from_seed([5; 16]);
+        let ids: Vec<PeerId> = (0..4).map(|_| PeerId::random_from(&mut rng)).collect();
+        let sketch = |id: &PeerId| {
+            let mut sketch = SizeEstimate::new(16);
+            sketch.observe(id);
+            sketch.hashes()
+        };
+        let mut estimate = SizeEstimate::new(16);
+        estimate.observe(&ids[0]);
+
+        estimate.merge(1, &sketch(&ids[1]));
+        assert_eq!(estimate.epoch(), 1);
+        // A straggler's sketch still counts towards the previous epoch
+        estimate.merge(0, &sketch(&ids[2]));
+        assert_eq!(estimate.estimate(), 3);
+
+        // Epochs further ahead only move us on to the next one
+        estimate.merge(3, &sketch(&ids[3]));
+        assert_eq!(estimate.epoch(), 2);
+        assert_eq!(estimate.estimate(), 1);
+        estimate.merge(0, &sketch(&ids[2]));
+        assert_eq!(estimate.estimate(), 1);
+    }
+
+    #[test]
+    fn withstand_the_maximal_epoch() {
+        let mut rng = SmallRng::from_seed([5; 16]);
+        let mut remote = SizeEstimate::new(16);
+        remote.observe(&PeerId::random_from(&mut rng));
+        let mut estimate = SizeEstimate::new(16);
+        estimate.observe(&PeerId::random_from(&mut rng));
+
+        estimate.merge(u64::max_value(), &remote.hashes());
+        assert_eq!(estimate.epoch(), 1);
+        assert_eq!(estimate.estimate(), 1);
+
+        estimate.epoch = u64::max_value();
+        estimate.tick(1);
+        estimate.merge(u64::max_value(), &remote.hashes());
+        assert_eq!(estimate.epoch(), u64::max_value());
+    }
+}
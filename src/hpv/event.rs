@@ -0,0 +1,41 @@
+use super::actix::{Message, Recipient};
+use super::futures::sync::mpsc::UnboundedReceiver;
+use super::Peer;
+use std::io;
+
+/// Notable changes in the membership of a `HyParView` instance
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum Event {
+    /// A peer was seen for the first time, or again after it had left both views
+    PeerDiscovered(Peer),
+    ActivePeerAdded(Peer),
+    ActivePeerRemoved(Peer),
+    PassivePeerAdded(Peer),
+    /// A peer left the passive view without being promoted to the active view
+    PassivePeerEvicted(Peer),
+    /// A peer acknowledged a join by taking us into its active view. This is the contact, unless a
+    /// peer that the join was forwarded to acknowledged it first.
+    JoinCompleted(Peer),
+    /// None of the contacts of the last join acknowledged it in time
+    JoinFailed,
+}
+
+impl Message for Event {
+    type Result = Result<(), io::Error>;
+}
+
+pub type EventRecipient = Recipient<Event>;
+
+/// Delivers all subsequent events to the enclosed recipient
+pub struct Subscribe(pub EventRecipient);
+
+impl Message for Subscribe {
+    type Result = Result<(), io::Error>;
+}
+
+/// Requests a stream of all subsequent events, for consumers outside of actix
+pub struct SubscribeStream;
+
+impl Message for SubscribeStream {
+    type Result = UnboundedReceiver<Event>;
+}
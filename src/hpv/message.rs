@@ -1,24 +1,70 @@
 use super::actix::Message;
-use super::{Peer, ViewsRecipient};
+use super::{Config, ConfigError, Peer, ViewsRecipient};
 use std::collections::HashSet;
 use std::fmt;
 use std::io;
+use std::net::SocketAddr;
+
+/// Requests a snapshot of the current `Views`, delivered to the enclosed recipient
+pub struct Inspect(pub ViewsRecipient);
+
+impl Message for Inspect {
+    type Result = Result<(), io::Error>;
+}
+
+/// Leaves the overlay and stops the actor. With `handoff`, the active neighbours also receive part
+/// of the passive view, to make up for the lost links.
+pub struct Leave {
+    pub handoff: bool,
+}
+
+impl Message for Leave {
+    type Result = Result<(), io::Error>;
+}
+
+/// Applies a new configuration, unless it is invalid, and at once disconnects the active peers that
+/// no longer fit
+pub struct SetConfig(pub Config);
+
+impl Message for SetConfig {
+    type Result = Result<(), ConfigError>;
+}
+
+/// Joins the overlay through the first of the contacts, tried in order, that acknowledges the join
+/// within the configured timeout
+pub struct InitiateJoin(pub Vec<SocketAddr>);
+
+impl Message for InitiateJoin {
+    type Result = Result<JoinResult, io::Error>;
+}
+
+/// How an `InitiateJoin` request ended
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum JoinResult {
+    /// Joined through the enclosed peer
+    JoinCompleted(Peer),
+    /// None of the contacts acknowledged the join in time
+    JoinFailed,
+}
 
 #[derive(Eq, PartialEq, Clone)]
 pub enum HpvMsg {
-    Inspect(ViewsRecipient),
-    InitiateJoin(Peer),
     Join(Peer),
+    /// Acknowledges a join, after which the contact has the joining peer in its active view
+    JoinReply(Peer),
     ForwardJoin {
         joining: Peer,
         forwarder: Peer,
         ttl: usize,
     },
+    /// `id` is echoed by the reply, to tell it apart from the replies to earlier requests
     Neighbour {
+        id: u32,
         peer: Peer,
         prio: bool,
     },
     NeighbourReply {
+        id: u32,
         peer: Peer,
         accepted: bool,
     },
@@ -29,15 +75,50 @@ pub enum HpvMsg {
         ttl: usize,
     },
     ShuffleReply(u32, HashSet<Peer>),
-    Disconnect(Peer),
+    /// `alive` tells whether the peer merely dropped us for capacity or is leaving the overlay
+    Disconnect {
+        peer: Peer,
+        alive: bool,
+    },
+    Heartbeat(Peer),
+    /// Passive peers left behind by a departing peer
+    Handoff(HashSet<Peer>),
+    /// Asks a passive peer whether it is still alive
+    Probe(Peer),
+    ProbeReply(Peer),
+    /// The sketch of the current epoch, from which the sender estimates the size of the overlay
+    SizeSketch {
+        epoch: u64,
+        hashes: Vec<u64>,
+    },
+}
+
+impl HpvMsg {
+    /// The peer that sent this message, if the message tells
+    pub fn sender(&self) -> Option<&Peer> {
+        match self {
+            HpvMsg::Join(p)
+            | HpvMsg::JoinReply(p)
+            | HpvMsg::Heartbeat(p)
+            | HpvMsg::Probe(p)
+            | HpvMsg::ProbeReply(p) => Some(p),
+            HpvMsg::ForwardJoin { forwarder, .. } => Some(forwarder),
+            HpvMsg::Neighbour { peer, .. }
+            | HpvMsg::NeighbourReply { peer, .. }
+            | HpvMsg::Disconnect { peer, .. } => Some(peer),
+            HpvMsg::Shuffle { .. }
+            | HpvMsg::ShuffleReply(_, _)
+            | HpvMsg::Handoff(_)
+            | HpvMsg::SizeSketch { .. } => None,
+        }
+    }
 }
 
 impl fmt::Debug for HpvMsg {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            HpvMsg::Inspect(_) => write!(f, "Inspect"),
-            HpvMsg::InitiateJoin(p) => write!(f, "InitiateJoin({})", p),
             HpvMsg::Join(p) => write!(f, "Join({})", p),
+            HpvMsg::JoinReply(p) => write!(f, "JoinReply({})", p),
             // FIXME: Somehow cannot be destructured without a fmt macro error...?
             HpvMsg::ForwardJoin { .. } => write!(f, "ForwardJoin()"),
             // FIXME: Somehow cannot be destructured without a fmt macro error...?
@@ -45,7 +126,12 @@ impl fmt::Debug for HpvMsg {
             HpvMsg::NeighbourReply { .. } => write!(f, "NeighbourReply()"),
             HpvMsg::Shuffle { .. } => write!(f, "Shuffle()"),
             HpvMsg::ShuffleReply(_, _) => write!(f, "ShuffleReply()"),
-            HpvMsg::Disconnect(p) => write!(f, "Disconnect({})", p),
+            HpvMsg::Disconnect { .. } => write!(f, "Disconnect()"),
+            HpvMsg::Heartbeat(p) => write!(f, "Heartbeat({})", p),
+            HpvMsg::Handoff(_) => write!(f, "Handoff()"),
+            HpvMsg::Probe(p) => write!(f, "Probe({})", p),
+            HpvMsg::ProbeReply(p) => write!(f, "ProbeReply({})", p),
+            HpvMsg::SizeSketch { .. } => write!(f, "SizeSketch()"),
         }
     }
 }
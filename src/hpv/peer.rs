@@ -1,16 +1,100 @@
-use super::HpvRecipient;
-use std::collections::hash_map::DefaultHasher;
+extern crate rand;
+
+use self::rand::Rng;
+use std::cmp::Ordering;
 use std::fmt;
 use std::hash::*;
+use std::net::SocketAddr;
+
+/// A stable identity for a node, independent of where it can currently be reached
+#[derive(Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy)]
+pub struct PeerId([u8; 16]);
+
+impl PeerId {
+    /// Draws a fresh, random (UUID v4-like) identity
+    pub fn random() -> PeerId {
+        PeerId::random_from(&mut rand::thread_rng())
+    }
+
+    pub fn random_from<R: Rng>(rng: &mut R) -> PeerId {
+        let mut bytes = [0u8; 16];
+        rng.fill(&mut bytes);
+        PeerId(bytes)
+    }
 
-#[derive(Eq, PartialEq, Hash, Clone)]
+    pub fn from_bytes(bytes: [u8; 16]) -> PeerId {
+        PeerId(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+
+    /// Parses the hex form that `Display` writes
+    pub fn from_hex(hex: &str) -> Option<PeerId> {
+        if hex.len() != 32 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let mut bytes = [0u8; 16];
+        for (i, b) in bytes.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+        }
+        Some(PeerId(bytes))
+    }
+}
+
+impl fmt::Debug for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self, f)
+    }
+}
+
+impl fmt::Display for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for b in self.0.iter() {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+/// A node in the overlay: its identity plus the address it can be reached at. Equality, ordering
+/// and hashing only consider the identity, so views are keyed by `PeerId`.
+#[derive(Clone)]
 pub struct Peer {
-    pub recipient: HpvRecipient,
+    pub id: PeerId,
+    pub addr: SocketAddr,
 }
 
-impl Into<Peer> for HpvRecipient {
-    fn into(self) -> Peer {
-        Peer { recipient: self }
+impl Peer {
+    pub fn new(id: PeerId, addr: SocketAddr) -> Peer {
+        Peer { id: id, addr: addr }
+    }
+}
+
+impl PartialEq for Peer {
+    fn eq(&self, other: &Peer) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Peer {}
+
+impl Hash for Peer {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl PartialOrd for Peer {
+    fn partial_cmp(&self, other: &Peer) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Peer {
+    fn cmp(&self, other: &Peer) -> Ordering {
+        self.id.cmp(&other.id)
     }
 }
 
@@ -19,10 +103,39 @@ impl fmt::Debug for Peer {
         fmt::Display::fmt(&self, f)
     }
 }
+
 impl fmt::Display for Peer {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut hasher = DefaultHasher::new();
-        self.recipient.hash(&mut hasher);
-        write!(f, "Peer {}", hasher.finish())
+        write!(f, "Peer {}@{}", self.id, self.addr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Peer, PeerId};
+    use std::net::SocketAddr;
+
+    #[test]
+    fn identity_determines_equality() {
+        let id = PeerId::random();
+        let a: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        let b: SocketAddr = "127.0.0.1:4001".parse().unwrap();
+
+        assert_eq!(Peer::new(id, a), Peer::new(id, b));
+        assert!(Peer::new(id, a) != Peer::new(PeerId::random(), a));
+    }
+
+    #[test]
+    fn display_id_as_hex() {
+        let id = PeerId::from_bytes([0xab; 16]);
+        assert_eq!(format!("{}", id), "ab".repeat(16));
+    }
+
+    #[test]
+    fn parse_hex_id() {
+        let id = PeerId::random();
+        assert_eq!(PeerId::from_hex(&id.to_string()), Some(id));
+        assert_eq!(PeerId::from_hex("ab"), None);
+        assert_eq!(PeerId::from_hex(&"+f".repeat(16)), None);
     }
 }
@@ -0,0 +1,1102 @@
+extern crate rand;
+
+use self::rand::rngs::SmallRng;
+use self::rand::{FromEntropy, Rng, RngCore, SeedableRng};
+use super::{AdaptiveSizing, Config, ConfigError, Event, HpvMsg, Peer, SizeEstimate, Snapshot};
+use bounded_set::BoundedSet;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::mem;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Timers that `HyParView` asks its environment to schedule
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Debug)]
+pub enum Timer {
+    Shuffle,
+    Heartbeat,
+    /// Gives up on the reply to the shuffle request with the given id
+    ShuffleTimeout(u32),
+    /// Gives up on the reply to the neighbour request with the given id
+    NeighbourTimeout(u32),
+    /// Gives up on the acknowledgement of the join attempt with the given number
+    JoinTimeout(u32),
+    /// Tries the seeds again, if we are still isolated
+    SeedRetry,
+    /// Drops the passive peer probed last, if it did not answer, and probes the next one
+    Probe,
+}
+
+/// The effects of handling an input, to be carried out by the environment hosting `HyParView`
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum Action {
+    /// Dispatch the message to the peer reachable at the address
+    Send(SocketAddr, HpvMsg),
+    /// Notify subscribers of a change in membership
+    Emit(Event),
+    /// Feed the timer back through `HyParView::handle_timer` once the duration has elapsed
+    Schedule(Timer, Duration),
+}
+
+/// The HyParView membership protocol as a synchronous state machine. It performs no I/O; every
+/// input returns the actions that should follow from it.
+pub struct HyParView {
+    self_peer: Peer,
+    pub(super) config: Config,
+    pub(super) active_view: BoundedSet<Peer>,
+    pub(super) passive_view: BoundedSet<Peer>,
+    next_shuffle_id: u32,
+    // the peers offered by each shuffle request that awaits its reply
+    outstanding_shuffles: HashMap<u32, HashSet<Peer>>,
+    next_neighbour_id: u32,
+    // the passive peers asked to become active, with the id and priority of each request
+    pub(super) pending_neighbours: BTreeMap<Peer, (u32, bool)>,
+    // passive peers that rejected us since the active view last started to be replenished
+    declined_neighbours: HashSet<Peer>,
+    // heartbeat rounds since each active peer was last heard from
+    missed_heartbeats: HashMap<Peer, usize>,
+    // source of every random protocol decision
+    rng: Box<RngCore + Send>,
+    // the views as last reported through events
+    reported_active: BTreeSet<Peer>,
+    reported_passive: BTreeSet<Peer>,
+    // the passive peer whose probe awaits its reply
+    probing: Option<Peer>,
+    // counts the peers we heard of, directly or through the sketches of others
+    size_estimate: SizeEstimate,
+    // the contact address of a join that has not been acknowledged yet
+    joining_through: Option<SocketAddr>,
+    // the peer that acknowledged the pending join, to be reported along with the view changes
+    completed_join: Option<Peer>,
+    // the contacts to try next should the current join attempt time out
+    join_contacts: VecDeque<SocketAddr>,
+    join_attempt: u32,
+    // whether a join through the seeds is underway or scheduled
+    seeding: bool,
+    // the delay before the seeds are tried again, doubled after every failed round
+    seed_backoff: Duration,
+    // set by `leave`, after which the seeds are no longer tried
+    left: bool,
+    actions: Vec<Action>,
+}
+
+impl HyParView {
+    /// Creates a `HyParView` whose random choices are seeded by `config.seed`, if any, or fails if
+    /// `config` is invalid
+    pub fn new(self_peer: Peer, config: Config) -> Result<HyParView, ConfigError> {
+        let rng = new_rng(config.seed);
+        HyParView::with_rng(self_peer, config, rng)
+    }
+
+    /// Like `new`, but draws every random choice (sampling, shuffle targets, evictions) from `rng`
+    pub fn with_rng(
+        self_peer: Peer,
+        config: Config,
+        rng: Box<RngCore + Send>,
+    ) -> Result<HyParView, ConfigError> {
+        config.validate()?;
+        let seed_backoff = config.seed_backoff;
+        let sketch_size = config
+            .adaptive_sizing
+            .as_ref()
+            .unwrap_or(&AdaptiveSizing::default())
+            .sketch_size;
+        let mut size_estimate = SizeEstimate::new(sketch_size);
+        size_estimate.observe(&self_peer.id);
+        Ok(HyParView {
+            self_peer: self_peer,
+            active_view: BoundedSet::with_policy(
+                config.max_active_view_size,
+                config.active_eviction,
+            ),
+            passive_view: BoundedSet::with_policy(
+                config.max_passive_view_size,
+                config.passive_eviction,
+            ),
+            config: config,
+            next_shuffle_id: 0,
+            outstanding_shuffles: HashMap::new(),
+            next_neighbour_id: 0,
+            pending_neighbours: BTreeMap::new(),
+            declined_neighbours: HashSet::new(),
+            missed_heartbeats: HashMap::new(),
+            rng: rng,
+            reported_active: BTreeSet::new(),
+            reported_passive: BTreeSet::new(),
+            probing: None,
+            size_estimate: size_estimate,
+            joining_through: None,
+            completed_join: None,
+            join_contacts: VecDeque::new(),
+            join_attempt: 0,
+            seeding: false,
+            seed_backoff: seed_backoff,
+            left: false,
+            actions: Vec::new(),
+        })
+    }
+
+    pub fn self_peer(&self) -> &Peer {
+        &self.self_peer
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The estimated number of peers in the overlay, including ourselves
+    pub fn estimated_size(&self) -> usize {
+        self.size_estimate.estimate()
+    }
+
+    /// Applies `config`, unless it is invalid. Views that shrink below their current size evict
+    /// peers by their policy, and the returned actions disconnect the demoted active peers.
+    pub fn set_config(&mut self, config: Config) -> Result<Vec<Action>, ConfigError> {
+        config.validate()?;
+        let previous_seed = self.config.seed;
+        self.config = config;
+        self.apply_seed_config(previous_seed);
+        self.apply_view_config();
+        Ok(self.take_actions())
+    }
+
+    /// Changes the configuration in place. Unlike `set_config`, this skips validation, so that
+    /// tests can set up corner cases; it does not exist outside of tests.
+    #[cfg(test)]
+    pub(crate) fn change_config<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&mut Config) -> (),
+    {
+        let previous_seed = self.config.seed;
+        f(&mut self.config);
+        self.apply_seed_config(previous_seed);
+        self.apply_view_config();
+    }
+
+    pub fn add_passive_node(&mut self, p: Peer) -> &mut Self {
+        self.passive_view.insert(p);
+        self
+    }
+
+    pub fn add_active_node(&mut self, p: Peer) -> &mut Self {
+        self.active_view.insert(p);
+        self
+    }
+
+    pub fn add_passive_view(&mut self, peers: HashSet<Peer>) -> &mut Self {
+        peers.iter().cloned().for_each(|p| {
+            self.passive_view.insert(p);
+        });
+        self
+    }
+
+    pub fn add_active_view(&mut self, peers: HashSet<Peer>) -> &mut Self {
+        peers.iter().cloned().for_each(|p| {
+            self.active_view.insert(p);
+        });
+        self
+    }
+
+    pub(super) fn add_outstanding_shuffle(&mut self, id: u32, offer: HashSet<Peer>) -> &mut Self {
+        self.outstanding_shuffles.insert(id, offer);
+        self.next_shuffle_id = self.next_shuffle_id.max(id.wrapping_add(1));
+        self
+    }
+
+    // Restart the RNG only when a new seed is configured, so unrelated changes keep a run replayable
+    fn apply_seed_config(&mut self, previous_seed: Option<u64>) {
+        if self.config.seed.is_some() && self.config.seed != previous_seed {
+            self.rng = new_rng(self.config.seed);
+        }
+    }
+
+    // Peers that no longer fit are evicted as if they made room for a new peer: surplus active peers
+    // are disconnected and demoted
+    fn apply_view_config(&mut self) {
+        self.active_view.set_policy(self.config.active_eviction);
+        self.passive_view.set_policy(self.config.passive_eviction);
+        if let Some(ref sizing) = self.config.adaptive_sizing {
+            self.size_estimate.set_k(sizing.sketch_size);
+        }
+        self.passive_view
+            .set_capacity(self.config.max_passive_view_size, &mut *self.rng);
+        let demoted = self.active_view
+            .set_capacity(self.config.max_active_view_size, &mut *self.rng);
+        for peer in demoted {
+            self.missed_heartbeats.remove(&peer);
+            let disconnect = HpvMsg::Disconnect {
+                peer: self.self_peer.clone(),
+                alive: true,
+            };
+            self.send(&peer, disconnect);
+            self.add_node_to_passive_view(peer);
+        }
+    }
+
+    /// Drains the actions accumulated since the last call
+    pub fn take_actions(&mut self) -> Vec<Action> {
+        self.join_seeds_when_isolated();
+        self.report_view_changes();
+        mem::replace(&mut self.actions, Vec::new())
+    }
+
+    /// Emits events for the net changes to both views since they were last reported. Comparing
+    /// snapshots, rather than tracking every insert and remove, hides transient changes.
+    fn report_view_changes(&mut self) {
+        let active: BTreeSet<Peer> = self.active_view.iter().cloned().collect();
+        let passive: BTreeSet<Peer> = self.passive_view.iter().cloned().collect();
+
+        let mut events = Vec::new();
+        for p in self.reported_active.difference(&active) {
+            events.push(Event::ActivePeerRemoved(p.clone()));
+        }
+        for p in self.reported_passive.difference(&passive) {
+            if !active.contains(p) {
+                events.push(Event::PassivePeerEvicted(p.clone()));
+            }
+        }
+        for p in active.difference(&self.reported_active) {
+            events.push(Event::ActivePeerAdded(p.clone()));
+        }
+        for p in passive.difference(&self.reported_passive) {
+            events.push(Event::PassivePeerAdded(p.clone()));
+        }
+        if let Some(through) = self.completed_join.take() {
+            if active.contains(&through) && self.joining_through.take().is_some() {
+                self.join_contacts.clear();
+                self.seeding = false;
+                self.seed_backoff = self.config.seed_backoff;
+                events.push(Event::JoinCompleted(through));
+            }
+        }
+
+        self.actions
+            .extend(events.into_iter().map(|e| Action::Emit(e)));
+        self.reported_active = active;
+        self.reported_passive = passive;
+    }
+
+    fn send(&mut self, to: &Peer, msg: HpvMsg) {
+        self.actions.push(Action::Send(to.addr, msg));
+    }
+}
+
+impl HyParView {
+    /// Initial actions, to be carried out once the environment is ready to process them
+    pub fn start(&mut self) -> Vec<Action> {
+        self.actions
+            .push(Action::Schedule(Timer::Shuffle, self.config.shuffle_interval));
+        self.actions
+            .push(Action::Schedule(Timer::Heartbeat, self.config.heartbeat_interval));
+        if let Some(interval) = self.config.passive_probe_interval {
+            self.actions.push(Action::Schedule(Timer::Probe, interval));
+        }
+        self.take_actions()
+    }
+
+    /// Returns to the peers of a snapshot taken before a restart, to be called before `start`. All
+    /// of them enter the passive view, and those that were active are asked to become neighbours
+    /// again first. The seeds are only tried once every request failed.
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        // The oldest peers go in first, to be evicted first should the passive view overflow, and
+        // the formerly active peers last, as the freshest
+        let mut peers = snapshot.peers;
+        peers.sort_by(|a, b| a.active.cmp(&b.active).then(b.age.cmp(&a.age)));
+        for p in peers.iter() {
+            self.add_node_to_passive_view(p.peer());
+        }
+
+        let formerly_active: Vec<Peer> = peers
+            .iter()
+            .rev()
+            .filter(|p| p.active)
+            .map(|p| p.peer())
+            .filter(|p| self.passive_view.contains(p))
+            .collect();
+        for peer in formerly_active {
+            if self.active_view.len() + self.pending_neighbours.len() < self.active_view.capacity {
+                self.send_neighbour_request(peer, true);
+            }
+        }
+        self.request_neighbours();
+    }
+
+    pub fn handle(&mut self, msg: HpvMsg) -> Vec<Action> {
+        // Hearing from a peer counts as using it, for the `LeastRecentlyUsed` eviction policy, and
+        // shows it is still around, for the size estimate
+        if let Some(sender) = msg.sender() {
+            self.active_view.touch(sender);
+            self.passive_view.touch(sender);
+            self.size_estimate.observe(&sender.id);
+        }
+        match msg {
+            HpvMsg::Join(p) => self.handle_join(p),
+            HpvMsg::JoinReply(p) => self.handle_join_reply(p),
+            HpvMsg::ForwardJoin {
+                joining,
+                forwarder,
+                ttl,
+            } => self.handle_forward_join(joining, forwarder, ttl),
+            HpvMsg::Neighbour { id, peer, prio } => self.handle_neighbour(id, peer, prio),
+            HpvMsg::NeighbourReply { id, peer, accepted } => {
+                self.handle_neighbour_reply(id, peer, accepted)
+            }
+            HpvMsg::Shuffle {
+                id,
+                origin,
+                exchange,
+                ttl,
+            } => self.handle_shuffle(id, origin, exchange, ttl),
+            HpvMsg::ShuffleReply(id, ps) => self.handle_shuffle_reply(id, ps),
+            HpvMsg::Disconnect { peer, alive } => self.handle_disconnect(&peer, alive),
+            HpvMsg::Heartbeat(p) => self.handle_heartbeat(&p),
+            HpvMsg::Handoff(peers) => self.handle_handoff(peers),
+            HpvMsg::Probe(p) => self.handle_probe(&p),
+            HpvMsg::ProbeReply(p) => self.handle_probe_reply(&p),
+            HpvMsg::SizeSketch { epoch, hashes } => self.handle_size_sketch(epoch, &hashes),
+        };
+        self.take_actions()
+    }
+
+    pub fn handle_timer(&mut self, timer: Timer) -> Vec<Action> {
+        match timer {
+            Timer::Shuffle => {
+                self.age_passive_view();
+                self.advance_size_estimate();
+                self.adapt_view_sizes();
+                self.initiate_shuffle();
+                self.actions
+                    .push(Action::Schedule(Timer::Shuffle, self.config.shuffle_interval));
+            }
+            Timer::Heartbeat => {
+                self.check_heartbeats();
+                self.actions
+                    .push(Action::Schedule(Timer::Heartbeat, self.config.heartbeat_interval));
+            }
+            Timer::ShuffleTimeout(id) => self.handle_shuffle_timeout(id),
+            Timer::NeighbourTimeout(id) => self.handle_neighbour_timeout(id),
+            Timer::JoinTimeout(attempt) => self.handle_join_timeout(attempt),
+            Timer::SeedRetry => self.handle_seed_retry(),
+            Timer::Probe => {
+                self.probe_passive_view();
+                if let Some(interval) = self.config.passive_probe_interval {
+                    self.actions.push(Action::Schedule(Timer::Probe, interval));
+                }
+            }
+        };
+        self.take_actions()
+    }
+
+    /// Signals that messages can no longer be delivered to the address, e.g. because the transport
+    /// lost its connection
+    pub fn handle_unreachable(&mut self, addr: SocketAddr) -> Vec<Action> {
+        let failed: Vec<Peer> = self.active_view
+            .iter()
+            .chain(self.passive_view.iter())
+            .filter(|p| p.addr == addr)
+            .cloned()
+            .collect();
+        for peer in failed {
+            self.handle_peer_failure(&peer);
+        }
+        // No need to wait for the join to time out
+        if self.joining_through == Some(addr) {
+            self.try_next_join_contact();
+        }
+        self.take_actions()
+    }
+
+    pub fn handle_heartbeat(&mut self, peer: &Peer) {
+        if self.active_view.contains(peer) {
+            self.missed_heartbeats.insert(peer.clone(), 0);
+        }
+    }
+
+    /// Counts a missed heartbeat for every active peer, fails those that exceed the configured
+    /// maximum and sends a heartbeat to the others
+    pub fn check_heartbeats(&mut self) {
+        let active: Vec<Peer> = self.active_view.iter().cloned().collect();
+        self.missed_heartbeats.retain(|p, _| active.contains(p));
+
+        let mut failed = Vec::new();
+        for peer in active {
+            let missed = {
+                let missed = self.missed_heartbeats.entry(peer.clone()).or_insert(0);
+                *missed += 1;
+                *missed
+            };
+            if missed > self.config.max_missed_heartbeats {
+                failed.push(peer);
+            } else {
+                let heartbeat = HpvMsg::Heartbeat(self.self_peer.clone());
+                self.send(&peer, heartbeat);
+            }
+        }
+
+        for peer in failed {
+            self.handle_peer_failure(&peer);
+        }
+    }
+
+    /// Reactive repair: forget the failed peer entirely and refill the active view from the
+    /// passive view
+    pub fn handle_peer_failure(&mut self, failed: &Peer) {
+        self.passive_view.remove(failed);
+        let requested = self.pending_neighbours.remove(failed).is_some();
+        if self.active_view.remove(failed) {
+            self.missed_heartbeats.remove(failed);
+            self.replenish_active_view();
+        } else if requested {
+            self.request_neighbours();
+        }
+    }
+
+    /// Leaves the overlay: active neighbours are told to replace us and, with `handoff`, each receives
+    /// a sample of our passive view. Afterwards both views are empty.
+    pub fn leave(&mut self, handoff: bool) -> Vec<Action> {
+        let active: Vec<Peer> = self.active_view.iter().cloned().collect();
+        for p in active {
+            if handoff {
+                let sample: HashSet<Peer> = self.passive_view
+                    .sample(&mut *self.rng, self.config.shuffle_passive)
+                    .into_iter()
+                    .cloned()
+                    .collect();
+                if !sample.is_empty() {
+                    self.send(&p, HpvMsg::Handoff(sample));
+                }
+            }
+            let disconnect = HpvMsg::Disconnect {
+                peer: self.self_peer.clone(),
+                alive: false,
+            };
+            self.send(&p, disconnect);
+        }
+
+        self.active_view =
+            BoundedSet::with_policy(self.active_view.capacity, self.active_view.policy());
+        self.passive_view =
+            BoundedSet::with_policy(self.passive_view.capacity, self.passive_view.policy());
+        self.missed_heartbeats.clear();
+        self.outstanding_shuffles.clear();
+        self.pending_neighbours.clear();
+        self.declined_neighbours.clear();
+        self.probing = None;
+        self.joining_through = None;
+        self.completed_join = None;
+        self.join_contacts.clear();
+        self.seeding = false;
+        self.left = true;
+        self.take_actions()
+    }
+
+    /// Handed off peers only take free slots of the passive view, they are no fresher than ours
+    pub fn handle_handoff(&mut self, peers: HashSet<Peer>) {
+        self.publish_peers(peers.clone());
+        let mut peers: Vec<Peer> = peers.into_iter().collect();
+        peers.sort();
+        for p in peers {
+            if !self.passive_view.is_full() {
+                self.add_node_to_passive_view(p);
+            }
+        }
+    }
+
+    /// Joins the overlay through the first of `contacts` that acknowledges the join in time. The
+    /// outcome is reported as either `JoinCompleted` or `JoinFailed`.
+    pub fn join(&mut self, contacts: Vec<SocketAddr>) -> Vec<Action> {
+        self.handle_init_join(contacts);
+        self.take_actions()
+    }
+
+    pub fn handle_init_join(&mut self, contacts: Vec<SocketAddr>) {
+        let self_addr = self.self_peer.addr;
+        self.join_contacts = contacts.into_iter().filter(|c| *c != self_addr).collect();
+        self.try_next_join_contact();
+    }
+
+    fn try_next_join_contact(&mut self) {
+        match self.join_contacts.pop_front() {
+            Some(contact) => {
+                self.join_attempt = self.join_attempt.wrapping_add(1);
+                self.joining_through = Some(contact);
+                let join = HpvMsg::Join(self.self_peer.clone());
+                self.actions.push(Action::Send(contact, join));
+                self.actions.push(Action::Schedule(
+                    Timer::JoinTimeout(self.join_attempt),
+                    self.config.join_timeout,
+                ));
+            }
+            None => {
+                self.joining_through = None;
+                self.actions.push(Action::Emit(Event::JoinFailed));
+                if self.seeding {
+                    println!(
+                        "[WARN] None of the seeds acknowledged our join, retrying in {:?}",
+                        self.seed_backoff
+                    );
+                    self.actions
+                        .push(Action::Schedule(Timer::SeedRetry, self.seed_backoff));
+                    self.seed_backoff = (self.seed_backoff * 2).min(self.config.max_seed_backoff);
+                }
+            }
+        }
+    }
+
+    /// The seeds other than ourselves, in random order
+    fn shuffled_seeds(&mut self) -> Vec<SocketAddr> {
+        let self_addr = self.self_peer.addr;
+        let mut seeds: Vec<SocketAddr> = self.config
+            .seed_addrs
+            .iter()
+            .cloned()
+            .filter(|s| *s != self_addr)
+            .collect();
+        self.rng.shuffle(&mut seeds);
+        seeds
+    }
+
+    fn is_isolated(&self) -> bool {
+        self.active_view.len() == 0 && self.passive_view.len() == 0
+            && self.pending_neighbours.is_empty() && self.joining_through.is_none()
+    }
+
+    // Both at startup and after losing every peer, the seeds are our only way back into the overlay
+    fn join_seeds_when_isolated(&mut self) {
+        if self.left || self.seeding || !self.is_isolated() {
+            return;
+        }
+        let seeds = self.shuffled_seeds();
+        if !seeds.is_empty() {
+            self.seeding = true;
+            self.handle_init_join(seeds);
+        }
+    }
+
+    fn handle_seed_retry(&mut self) {
+        if self.is_isolated() {
+            let seeds = self.shuffled_seeds();
+            self.handle_init_join(seeds);
+        } else if self.joining_through.is_none() {
+            self.seeding = false;
+        }
+    }
+
+    /// The contact, or a peer that the join was forwarded to, took us into its active view. It
+    /// enters ours as well, and the first to do so completes a pending join.
+    pub fn handle_join_reply(&mut self, contact: Peer) {
+        self.publish_peer(contact.clone());
+        self.passive_view.remove(&contact);
+        self.add_node_to_active_view(contact.clone());
+        if self.joining_through.is_some() && self.completed_join.is_none() {
+            self.completed_join = Some(contact);
+        }
+    }
+
+    fn handle_join_timeout(&mut self, attempt: u32) {
+        if attempt != self.join_attempt {
+            return;
+        }
+        if let Some(contact) = self.joining_through {
+            println!("[INFO] Join through {} timed out", contact);
+            self.try_next_join_contact();
+        }
+    }
+
+    pub fn handle_join(&mut self, new_peer: Peer) {
+        self.publish_peer(new_peer.clone());
+
+        if !self.active_view.contains(&new_peer) && self.active_view.is_full() {
+            self.drop_active_peer();
+        }
+        let forward_join = HpvMsg::ForwardJoin {
+            joining: new_peer.clone(),
+            forwarder: self.self_peer.clone(),
+            ttl: self.config.active_rwl,
+        };
+        let active: Vec<Peer> = self.active_view.iter().cloned().collect();
+        for p in active {
+            self.send(&p, forward_join.clone());
+        }
+        self.send(&new_peer, HpvMsg::JoinReply(self.self_peer.clone()));
+        self.promote_peer(new_peer);
+    }
+
+    pub fn handle_forward_join(&mut self, new_peer: Peer, forwarder: Peer, ttl: usize) {
+        self.publish_peer(new_peer.clone());
+
+        if ttl == 0 || self.active_view.len() == 0 {
+            self.accept_joining_peer(new_peer);
+        } else {
+            if ttl == self.config.passive_rwl {
+                self.add_node_to_passive_view(new_peer.clone());
+            }
+
+            if ttl > 0 {
+                // The candidates to forward to exclude the one who forwarded. Taking the forwarder out
+                // of the view instead could drop it, or take it in when it was not active at all.
+                let candidates: Vec<Peer> = self.active_view
+                    .iter()
+                    .filter(|p| **p != forwarder && **p != new_peer)
+                    .cloned()
+                    .collect();
+                if !candidates.is_empty() {
+                    let p = candidates[self.rng.gen_range(0, candidates.len())].clone();
+                    let msg = HpvMsg::ForwardJoin {
+                        joining: new_peer.clone(),
+                        forwarder: self.self_peer.clone(),
+                        ttl: ttl - 1,
+                    };
+                    self.send(&p, msg);
+                } else {
+                    // If we cannot forward, it's better to expand our active view
+                    self.accept_joining_peer(new_peer);
+                }
+            }
+        }
+    }
+
+    /// Ends a forwarded join by taking the joining peer into the active view. The joining peer is
+    /// told, so that it takes us in as well rather than fail us for not sending heartbeats.
+    fn accept_joining_peer(&mut self, new_peer: Peer) {
+        self.add_node_to_active_view(new_peer.clone());
+        if self.active_view.contains(&new_peer) {
+            self.send(&new_peer, HpvMsg::JoinReply(self.self_peer.clone()));
+        }
+    }
+
+    /// A peer that is `alive` merely dropped us and is kept as a passive peer, one that is leaving
+    /// the overlay is forgotten
+    pub fn handle_disconnect(&mut self, remove: &Peer, alive: bool) {
+        if self.active_view.contains(remove) {
+            self.active_view.remove(remove);
+        }
+        self.missed_heartbeats.remove(remove);
+        self.pending_neighbours.remove(remove);
+
+        // Ask for a replacement before demoting, so that we do not ask the same peer right back
+        self.replenish_active_view();
+        if alive {
+            self.add_node_to_passive_view(remove.clone());
+        } else {
+            self.passive_view.remove(remove);
+        }
+    }
+
+    /// Starts over asking passive peers to fill the active view, including those that rejected us
+    /// before
+    pub fn replenish_active_view(&mut self) {
+        self.declined_neighbours.clear();
+        self.request_neighbours();
+    }
+
+    /// Asks random passive peers to become active, until the active view would be full if they all
+    /// accept. Once the active view is empty, requests get priority, including those still pending.
+    pub fn request_neighbours(&mut self) {
+        let prio = self.active_view.len() == 0;
+        if prio {
+            let unprioritized: Vec<Peer> = self.pending_neighbours
+                .iter()
+                .filter(|&(_, &(_, p))| !p)
+                .map(|(peer, _)| peer.clone())
+                .collect();
+            for candidate in unprioritized {
+                self.send_neighbour_request(candidate, true);
+            }
+        }
+
+        while self.active_view.len() + self.pending_neighbours.len() < self.active_view.capacity {
+            let candidates: Vec<Peer> = self.passive_view
+                .iter()
+                .filter(|p| !self.pending_neighbours.contains_key(p))
+                .filter(|p| prio || !self.declined_neighbours.contains(p))
+                .cloned()
+                .collect();
+            if candidates.is_empty() {
+                break;
+            }
+            let candidate = candidates[self.rng.gen_range(0, candidates.len())].clone();
+            self.send_neighbour_request(candidate, prio);
+        }
+    }
+
+    fn send_neighbour_request(&mut self, candidate: Peer, prio: bool) {
+        let id = self.next_neighbour_id;
+        self.next_neighbour_id = self.next_neighbour_id.wrapping_add(1);
+        self.pending_neighbours.insert(candidate.clone(), (id, prio));
+        self.send(
+            &candidate,
+            HpvMsg::Neighbour {
+                id: id,
+                peer: self.self_peer.clone(),
+                prio: prio,
+            },
+        );
+        self.actions.push(Action::Schedule(
+            Timer::NeighbourTimeout(id),
+            self.config.neighbour_timeout,
+        ));
+    }
+
+    /// Makes room in the active view by demoting the peer its eviction policy picks
+    pub fn drop_active_peer(&mut self) {
+        match self.active_view.evict(&mut *self.rng) {
+            Some(node) => {
+                let disconnect = HpvMsg::Disconnect {
+                    peer: self.self_peer.clone(),
+                    alive: true,
+                };
+                self.send(&node, disconnect);
+                self.add_node_to_passive_view(node);
+            }
+            None => {
+                println!("Wanted to drop an active peer, but none found");
+            }
+        }
+    }
+
+    pub fn promote_peer(&mut self, new_peer: Peer) {
+        self.missed_heartbeats.insert(new_peer.clone(), 0);
+        self.active_view.insert(new_peer);
+    }
+
+    pub fn add_node_to_active_view(&mut self, new_peer: Peer) {
+        if new_peer != self.self_peer && !self.active_view.contains(&new_peer) {
+            if self.active_view.is_full() {
+                self.drop_active_peer();
+            }
+            self.promote_peer(new_peer);
+        }
+    }
+
+    pub fn add_node_to_passive_view(&mut self, new_peer: Peer) {
+        if new_peer != self.self_peer && !self.active_view.contains(&new_peer)
+            && !self.passive_view.contains(&new_peer)
+        {
+            self.passive_view.insert_evicting(new_peer, &mut *self.rng);
+        }
+    }
+
+    /// Ranks a peer for the `ScoreBased` eviction policy, higher scores being evicted last
+    pub fn set_score(&mut self, peer: &Peer, score: i64) {
+        self.active_view.set_score(peer, score);
+        self.passive_view.set_score(peer, score);
+    }
+
+    /// Starts a new shuffle round for the ages of the passive peers
+    pub fn age_passive_view(&mut self) {
+        self.passive_view.tick();
+    }
+
+    /// Drops the passive peer probed last if it did not answer, then probes the passive peer that is
+    /// next in line for eviction, unless it is being asked to become a neighbour already
+    pub fn probe_passive_view(&mut self) {
+        if let Some(silent) = self.probing.take() {
+            if self.passive_view.remove(&silent) {
+                println!("[INFO] Passive peer {} did not answer its probe, dropping it", silent);
+            }
+        }
+
+        let candidate = self.passive_view
+            .eviction_order(&mut *self.rng)
+            .into_iter()
+            .find(|p| !self.pending_neighbours.contains_key(p))
+            .cloned();
+        if let Some(candidate) = candidate {
+            self.send(&candidate, HpvMsg::Probe(self.self_peer.clone()));
+            self.probing = Some(candidate);
+        }
+    }
+
+    pub fn handle_probe(&mut self, prober: &Peer) {
+        self.send(prober, HpvMsg::ProbeReply(self.self_peer.clone()));
+    }
+
+    /// The reply already refreshed the age of the peer, which just needs to be cleared as probed
+    pub fn handle_probe_reply(&mut self, peer: &Peer) {
+        if self.probing.as_ref() == Some(peer) {
+            self.probing = None;
+        }
+    }
+
+    pub fn handle_neighbour(&mut self, id: u32, neighbour: Peer, prio: bool) {
+        self.publish_peer(neighbour.clone());
+
+        if prio && self.active_view.is_full() {
+            self.drop_active_peer();
+        }
+
+        if self.active_view.is_full() {
+            self.send(
+                &neighbour,
+                HpvMsg::NeighbourReply {
+                    id: id,
+                    peer: self.self_peer.clone(),
+                    accepted: false,
+                },
+            );
+        } else {
+            self.send(
+                &neighbour,
+                HpvMsg::NeighbourReply {
+                    id: id,
+                    peer: self.self_peer.clone(),
+                    accepted: true,
+                },
+            );
+
+            self.promote_peer(neighbour.clone());
+            self.passive_view.remove(&neighbour);
+            self.pending_neighbours.remove(&neighbour);
+        }
+    }
+
+    /// Candidates only become active peers once they accept, those that reject us are not asked
+    /// again until the active view is replenished anew. A request repeated with priority may still
+    /// be answered by a rejection of the original, which does not count against the repetition.
+    pub fn handle_neighbour_reply(&mut self, id: u32, neighbour: Peer, accepted: bool) {
+        self.publish_peer(neighbour.clone());
+
+        let latest = self.pending_neighbours
+            .get(&neighbour)
+            .map(|&(pending_id, _)| pending_id == id);
+        if accepted {
+            if latest.is_some() {
+                self.pending_neighbours.remove(&neighbour);
+                self.passive_view.remove(&neighbour);
+                self.add_node_to_active_view(neighbour);
+            } else if !self.active_view.contains(&neighbour) {
+                // We gave up on this request, so free the slot the peer made for us
+                let disconnect = HpvMsg::Disconnect {
+                    peer: self.self_peer.clone(),
+                    alive: true,
+                };
+                self.send(&neighbour, disconnect);
+            }
+        } else if latest == Some(true) {
+            self.pending_neighbours.remove(&neighbour);
+            self.declined_neighbours.insert(neighbour);
+            self.request_neighbours();
+        }
+    }
+
+    fn handle_neighbour_timeout(&mut self, id: u32) {
+        let expired = self.pending_neighbours
+            .iter()
+            .find(|&(_, &(pending_id, _))| pending_id == id)
+            .map(|(peer, _)| peer.clone());
+        if let Some(candidate) = expired {
+            println!("[INFO] Neighbour request ({}) to {} timed out", id, candidate);
+            // An unresponsive candidate has most likely failed
+            self.pending_neighbours.remove(&candidate);
+            self.passive_view.remove(&candidate);
+            self.request_neighbours();
+        }
+    }
+
+    pub fn initiate_shuffle(&mut self) {
+        // Without active peers, a passive peer is the only one left to learn about the overlay from
+        let passive_target = if self.active_view.len() == 0 {
+            self.passive_view.sample_one(&mut *self.rng).cloned()
+        } else {
+            None
+        };
+        let target = passive_target.or_else(|| self.active_view.sample_one(&mut *self.rng).cloned());
+        match target {
+            Some(shuffle_target) => {
+                // Clone the views to sample of them, without the shuffle target...?
+                // TODO: Improve!
+                let mut active = self.active_view.clone();
+                active.remove(&shuffle_target);
+                let mut passive = self.passive_view.clone();
+                passive.remove(&shuffle_target);
+                let exchange: HashSet<Peer> = {
+                    let active_part = active.sample(&mut *self.rng, self.config.shuffle_active);
+                    let passive_part = passive.sample(&mut *self.rng, self.config.shuffle_passive);
+                    active_part
+                        .union(&passive_part)
+                        .map(|e| (**e).clone())
+                        .collect()
+                };
+                let id = self.next_shuffle_id;
+                self.next_shuffle_id = self.next_shuffle_id.wrapping_add(1);
+                self.outstanding_shuffles.insert(id, exchange.clone());
+
+                let shuffle_request = HpvMsg::Shuffle {
+                    id: id,
+                    origin: self.self_peer.clone(),
+                    exchange: exchange,
+                    ttl: self.config.shuffle_rwl,
+                };
+                self.send(&shuffle_target, shuffle_request);
+                self.send_size_sketch(&shuffle_target);
+                self.actions.push(Action::Schedule(
+                    Timer::ShuffleTimeout(id),
+                    self.config.shuffle_timeout,
+                ));
+            }
+            None => {}
+        }
+
+        self.replenish_active_view();
+    }
+
+    /// Merges the sketch of another peer. Only ourselves and the peers we hear from enter a sketch,
+    /// never peers merely passed along, so that peers that left stop being counted. We add ourselves
+    /// again should a new epoch start here.
+    pub fn handle_size_sketch(&mut self, epoch: u64, hashes: &[u64]) {
+        self.size_estimate.merge(epoch, hashes);
+        self.size_estimate.observe(&self.self_peer.id);
+    }
+
+    fn send_size_sketch(&mut self, to: &Peer) {
+        if self.config.adaptive_sizing.is_some() {
+            let sketch = HpvMsg::SizeSketch {
+                epoch: self.size_estimate.epoch(),
+                hashes: self.size_estimate.hashes(),
+            };
+            self.send(to, sketch);
+        }
+    }
+
+    // Counts a shuffle round towards the epoch of the size estimate
+    fn advance_size_estimate(&mut self) {
+        let epoch_rounds = match self.config.adaptive_sizing {
+            Some(ref sizing) => sizing.sketch_epoch_rounds,
+            None => return,
+        };
+        self.size_estimate.tick(epoch_rounds);
+        self.size_estimate.observe(&self.self_peer.id);
+    }
+
+    /// With adaptive sizing, resizes both views to fit the estimated size of the overlay
+    pub fn adapt_view_sizes(&mut self) {
+        let estimate = self.size_estimate.estimate();
+        let (active, passive) = match self.config.adaptive_sizing {
+            Some(ref sizing) => sizing.view_sizes(estimate),
+            None => return,
+        };
+        if active != self.config.max_active_view_size || passive != self.config.max_passive_view_size
+        {
+            println!(
+                "[INFO] Resizing views to {} active and {} passive peers for an estimated {} peers",
+                active, passive, estimate
+            );
+            self.config.max_active_view_size = active;
+            self.config.max_passive_view_size = passive;
+            self.apply_view_config();
+        }
+    }
+
+    pub fn handle_shuffle(&mut self, id: u32, origin: Peer, exchange: HashSet<Peer>, ttl: usize) {
+        self.publish_peers(exchange.clone());
+
+        if ttl == 1 || self.active_view.len() <= 1 {
+            // construct a response with candidates from our passive view
+            let mut passive_fragment = self.passive_view.clone();
+            exchange.iter().for_each(|p| {
+                passive_fragment.remove(p);
+            });
+            passive_fragment.remove(&origin);
+            let sample: HashSet<Peer> = passive_fragment
+                .sample(&mut *self.rng, exchange.len() + 1)
+                .iter()
+                .map(|x| (**x).clone())
+                .collect();
+
+            self.send(&origin, HpvMsg::ShuffleReply(id, sample.clone()));
+            // Sketches travel both ways, or peers that are rarely picked would rarely hear of others
+            self.send_size_sketch(&origin);
+
+            let mut all_peers = exchange;
+            all_peers.insert(origin);
+            self.merge_into_passive_view(all_peers, &sample)
+        } else {
+            // FIXME: structural sharing would really start to be beneficial...
+            // The id belongs to the origin, which matches the eventual reply against it
+            let forward_message = HpvMsg::Shuffle {
+                id: id,
+                origin: origin.clone(),
+                exchange: exchange,
+                ttl: ttl - 1,
+            };
+            let mut active_fragment = self.active_view.clone();
+            active_fragment.remove(&origin);
+            match active_fragment.sample_one(&mut *self.rng) {
+                Some(target) => self.send(target, forward_message),
+                _ => {}
+            };
+        }
+    }
+
+    pub fn handle_shuffle_reply(&mut self, shuffle_reply_id: u32, exchange: HashSet<Peer>) {
+        self.publish_peers(exchange.clone());
+
+        match self.outstanding_shuffles.remove(&shuffle_reply_id) {
+            // The peers we offered are the first to make room for the ones we received
+            Some(offer) => self.merge_into_passive_view(exchange, &offer),
+            None => println!(
+                "[INFO] Received reply to unknown, answered or timed out shuffle request ({}), ignoring",
+                shuffle_reply_id
+            ),
+        }
+    }
+
+    fn handle_shuffle_timeout(&mut self, id: u32) {
+        if self.outstanding_shuffles.remove(&id).is_some() {
+            println!("[INFO] Shuffle request ({}) timed out", id);
+        }
+    }
+
+    /// Adds shuffled peers to the passive view, evicting those in `drop_priority` first. Ourselves
+    /// and peers in the active view are ignored.
+    fn merge_into_passive_view(&mut self, mut peers: HashSet<Peer>, drop_priority: &HashSet<Peer>) {
+        peers.remove(&self.self_peer);
+        let peers: HashSet<Peer> = peers
+            .into_iter()
+            .filter(|p| !self.active_view.contains(p))
+            .collect();
+        self.passive_view
+            .bounded_union(&peers, drop_priority, &mut *self.rng);
+    }
+
+    pub fn publish_peer(&mut self, peer: Peer) {
+        self.publish_peers(hashset!{peer});
+    }
+
+    pub fn publish_peers(&mut self, peers: HashSet<Peer>) {
+        let mut unknown: Vec<Peer> = peers
+            .into_iter()
+            .filter(|p| !self.active_view.contains(p))
+            .filter(|p| !self.passive_view.contains(p))
+            .collect();
+        unknown.sort();
+        for p in unknown {
+            self.actions.push(Action::Emit(Event::PeerDiscovered(p)));
+        }
+    }
+}
+
+fn new_rng(seed: Option<u64>) -> Box<RngCore + Send> {
+    match seed {
+        Some(seed) => Box::new(SmallRng::from_seed(expand_seed(seed))),
+        None => Box::new(SmallRng::from_entropy()),
+    }
+}
+
+/// Spreads a `u64` over the 16 seed bytes of `SmallRng` using SplitMix64, so that similar seeds
+/// still start unrelated streams
+pub fn expand_seed(seed: u64) -> [u8; 16] {
+    let mut state = seed;
+    let mut bytes = [0u8; 16];
+    for chunk in bytes.chunks_mut(8) {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        for (i, b) in chunk.iter_mut().enumerate() {
+            *b = (z >> (8 * i)) as u8;
+        }
+    }
+    bytes
+}
@@ -0,0 +1,157 @@
+extern crate serde;
+extern crate toml;
+
+use self::serde::de::{Deserialize, Deserializer, Error as DeError};
+use self::serde::ser::Serializer;
+use super::{Peer, PeerId, Views};
+use bounded_set::BoundedSet;
+use std::fs;
+use std::io;
+use std::io::ErrorKind::InvalidData;
+use std::net::SocketAddr;
+use std::path::Path;
+
+/// The peers of both views, persisted so that a restarted peer can return to its former
+/// neighbours instead of joining through a seed
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub peers: Vec<SnapshotPeer>,
+}
+
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct SnapshotPeer {
+    #[serde(serialize_with = "serialize_id", deserialize_with = "deserialize_id")]
+    pub id: PeerId,
+    pub addr: SocketAddr,
+    /// Whether the peer was in the active view, rather than the passive view
+    pub active: bool,
+    /// The `BoundedSet::age` of the peer within its view, which counts the shuffle rounds since a
+    /// passive peer was last heard from
+    pub age: u64,
+}
+
+impl SnapshotPeer {
+    pub fn peer(&self) -> Peer {
+        Peer::new(self.id, self.addr)
+    }
+}
+
+impl Snapshot {
+    pub fn from_views(views: &Views) -> Snapshot {
+        let mut peers = snapshot_peers(&views.active_view, true);
+        peers.extend(snapshot_peers(&views.passive_view, false));
+        Snapshot { peers: peers }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Snapshot> {
+        let text = fs::read_to_string(path)?;
+        toml::from_str(&text).map_err(|e| io::Error::new(InvalidData, e))
+    }
+
+    /// Writes a temporary file next to `path` and renames it into place, so that a crash while
+    /// saving leaves the previous snapshot intact
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let text = toml::to_string(self).map_err(|e| io::Error::new(InvalidData, e))?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, text)?;
+        fs::rename(&tmp, path)
+    }
+}
+
+fn snapshot_peers(view: &BoundedSet<Peer>, active: bool) -> Vec<SnapshotPeer> {
+    view.iter()
+        .map(|p| SnapshotPeer {
+            id: p.id,
+            addr: p.addr,
+            active: active,
+            age: view.age(p).unwrap_or(0),
+        })
+        .collect()
+}
+
+fn serialize_id<S: Serializer>(id: &PeerId, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&id.to_string())
+}
+
+fn deserialize_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PeerId, D::Error> {
+    let hex = String::deserialize(deserializer)?;
+    PeerId::from_hex(&hex).ok_or_else(|| D::Error::custom(format!("invalid peer id {:?}", hex)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+
+    fn peer(port: u16) -> Peer {
+        Peer::new(PeerId::random(), SocketAddr::from(([127, 0, 0, 1], port)))
+    }
+
+    #[test]
+    fn record_views_with_ages() {
+        let (active, old, fresh) = (peer(4000), peer(4001), peer(4002));
+        let mut views = Views {
+            active_view: BoundedSet::new(2),
+            passive_view: BoundedSet::new(2),
+        };
+        views.active_view.insert(active.clone());
+        views.passive_view.insert(old.clone());
+        views.passive_view.tick();
+        views.passive_view.insert(fresh.clone());
+
+        let mut peers = Snapshot::from_views(&views).peers;
+        peers.sort_by_key(|p| (!p.active, p.age));
+        assert_eq!(
+            peers.iter().map(|p| p.peer()).collect::<Vec<Peer>>(),
+            vec![active, fresh, old]
+        );
+        assert_eq!(
+            peers.iter().map(|p| p.age).collect::<Vec<u64>>(),
+            vec![0, 0, 1]
+        );
+    }
+
+    #[test]
+    fn save_and_load() {
+        let snapshot = Snapshot {
+            peers: vec![
+                SnapshotPeer {
+                    id: PeerId::random(),
+                    addr: "127.0.0.1:4000".parse().unwrap(),
+                    active: true,
+                    age: 0,
+                },
+                SnapshotPeer {
+                    id: PeerId::random(),
+                    addr: "[::1]:4001".parse().unwrap(),
+                    active: false,
+                    age: 7,
+                },
+            ],
+        };
+        let path = env::temp_dir().join(format!("hyparview-{}.toml", PeerId::random()));
+
+        snapshot.save(&path).unwrap();
+        let loaded = Snapshot::load(&path);
+        let _ = fs::remove_file(&path);
+        assert_eq!(loaded.unwrap(), snapshot);
+    }
+
+    #[test]
+    fn reject_malformed_snapshot() {
+        let path = env::temp_dir().join(format!("hyparview-{}.toml", PeerId::random()));
+        fs::write(
+            &path,
+            "[[peers]]\nid = \"xyz\"\naddr = \"127.0.0.1:4000\"\nactive = true\nage = 0\n",
+        ).unwrap();
+
+        let loaded = Snapshot::load(&path);
+        let _ = fs::remove_file(&path);
+        assert_eq!(loaded.unwrap_err().kind(), InvalidData);
+    }
+}
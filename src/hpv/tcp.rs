@@ -0,0 +1,197 @@
+use super::actix::prelude::*;
+use super::codec::{read_frame, write_frame, DecodeError, EncodeError};
+use super::futures::sync::oneshot;
+use super::futures::{future, Future};
+use super::{Dropped, HpvMsg, HpvRecipient, Outbound, Register};
+use std::collections::HashMap;
+use std::io;
+use std::io::ErrorKind::{AddrNotAvailable, AlreadyExists, BrokenPipe, InvalidInput, WouldBlock};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::Duration;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The number of messages that may wait for a slow peer before further messages to it are dropped
+const SEND_QUEUE_LEN: usize = 64;
+
+/// A message along with the sender of its outcome
+type Delivery = (HpvMsg, oneshot::Sender<io::Result<()>>);
+
+/// Carries `Outbound` messages to other processes over TCP, and delivers the messages it receives
+/// to the `HyParViewActor` registered at its listening address. Every peer gets a thread that dials
+/// it and writes its messages, so a dead or slow peer only delays its own messages. The outcome of
+/// a delivery is reported once it was written.
+pub struct TcpTransport {
+    listener: Option<TcpListener>,
+    local_addr: SocketAddr,
+    // the queue of the thread writing to each peer
+    writers: HashMap<SocketAddr, SyncSender<Delivery>>,
+}
+
+impl TcpTransport {
+    pub fn bind(addr: SocketAddr) -> io::Result<TcpTransport> {
+        let listener = TcpListener::bind(addr)?;
+        Ok(TcpTransport {
+            local_addr: listener.local_addr()?,
+            listener: Some(listener),
+            writers: HashMap::new(),
+        })
+    }
+
+    /// The address this transport accepts connections on; peers should advertise it
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    fn accept(listener: TcpListener, inbound: HpvRecipient) {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let inbound = inbound.clone();
+                    thread::spawn(move || TcpTransport::receive(stream, inbound));
+                }
+                Err(e) => println!("[WARN] Failed to accept connection: {}", e),
+            }
+        }
+    }
+
+    fn receive(mut stream: TcpStream, inbound: HpvRecipient) {
+        loop {
+            match read_frame(&mut stream) {
+                Ok(msg) => {
+                    if inbound.do_send(msg).is_err() {
+                        return;
+                    }
+                }
+                Err(DecodeError::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => return,
+                Err(e) => {
+                    println!("[WARN] Dropping connection after invalid frame: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Hands the delivery to the writer of its peer, starting a new writer if there is none or the
+    /// last one gave up on a broken connection
+    fn enqueue(&mut self, to: SocketAddr, delivery: Delivery) -> io::Result<()> {
+        let delivery = match self.writers.get(&to) {
+            Some(writer) => match writer.try_send(delivery) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Full(_)) => {
+                    return Err(io::Error::new(WouldBlock, Dropped::QueueFull(to)))
+                }
+                Err(TrySendError::Disconnected(delivery)) => delivery,
+            },
+            None => delivery,
+        };
+        let (writer, queue) = sync_channel(SEND_QUEUE_LEN);
+        thread::spawn(move || TcpTransport::write(to, queue));
+        let _ = writer.try_send(delivery);
+        self.writers.insert(to, writer);
+        Ok(())
+    }
+
+    // Writes until the connection breaks. Dropping the queue then fails the deliveries left in it.
+    fn write(to: SocketAddr, queue: Receiver<Delivery>) {
+        let stream = TcpStream::connect_timeout(&to, CONNECT_TIMEOUT).and_then(|stream| {
+            stream.set_nodelay(true)?;
+            Ok(stream)
+        });
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                println!("[WARN] Failed to connect to {}: {}", to, e);
+                return;
+            }
+        };
+        for (msg, done) in queue {
+            let result = write_frame(&mut stream, &msg);
+            if let Err(ref e) = result {
+                println!("[WARN] Failed to send message to {}: {}", to, e);
+            }
+            // A message that could not be encoded was never written, so the connection is intact
+            let (result, broken) = match result {
+                Ok(()) => (Ok(()), false),
+                Err(EncodeError::Io(e)) => (Err(e), true),
+                Err(e) => (Err(io::Error::new(InvalidInput, Dropped::Unencodable(e))), false),
+            };
+            let _ = done.send(result);
+            if broken {
+                return;
+            }
+        }
+    }
+}
+
+impl Actor for TcpTransport {
+    type Context = Context<Self>;
+}
+
+impl Handler<Register> for TcpTransport {
+    type Result = Result<(), io::Error>;
+
+    fn handle(&mut self, msg: Register, _ctx: &mut Context<Self>) -> Self::Result {
+        let Register(addr, inbound) = msg;
+        if addr != self.local_addr {
+            return Err(io::Error::new(
+                AddrNotAvailable,
+                format!("Transport listens on {}, not {}", self.local_addr, addr),
+            ));
+        }
+        match self.listener.take() {
+            Some(listener) => {
+                thread::spawn(move || TcpTransport::accept(listener, inbound));
+                Ok(())
+            }
+            None => Err(io::Error::new(
+                AlreadyExists,
+                "A recipient is already registered with this transport",
+            )),
+        }
+    }
+}
+
+impl Handler<Outbound> for TcpTransport {
+    type Result = ResponseFuture<(), io::Error>;
+
+    fn handle(&mut self, msg: Outbound, _ctx: &mut Context<Self>) -> Self::Result {
+        let Outbound { to, msg } = msg;
+        let (done, outcome) = oneshot::channel();
+        if let Err(e) = self.enqueue(to, (msg, done)) {
+            return Box::new(future::err(e));
+        }
+        Box::new(outcome.then(move |outcome| match outcome {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::new(
+                BrokenPipe,
+                format!("Connection to {} failed", to),
+            )),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hpv::{Peer, PeerId};
+
+    #[test]
+    fn drop_messages_to_slow_peer_once_queue_is_full() {
+        let mut transport = TcpTransport::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let to: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        // A writer that does not get to its queue, as if the peer were slow
+        let (writer, _queue) = sync_channel(1);
+        transport.writers.insert(to, writer);
+        let msg = HpvMsg::Heartbeat(Peer::new(PeerId::random(), to));
+
+        let (done, _) = oneshot::channel();
+        assert!(transport.enqueue(to, (msg.clone(), done)).is_ok());
+        let (done, _) = oneshot::channel();
+        let error = transport.enqueue(to, (msg, done)).unwrap_err();
+        assert!(Dropped::is_dropped(&error));
+        assert_eq!(error.kind(), WouldBlock);
+    }
+}
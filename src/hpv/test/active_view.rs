@@ -4,29 +4,36 @@ extern crate futures_channel;
 
 use self::actix::prelude::*;
 use super::*;
-use hpv::HpvMsg;
+use bounded_set::EvictionPolicy;
+use hpv::{Action, HpvMsg, SetConfig, Timer};
 
 #[test]
 fn promote_prioritized_when_final_active_disconnects() {
     let _ = System::new("test");
     let (_, actv_probe) = mock_hpv_peer();
     let (pp, pasv_probe) = mock_hpv_peer();
-    let (_, mock_self) = mock_hpv_peer();
 
-    let (_, mut hpv) = new_hyparview(|x| {
+    let mut hpv = new_hyparview(|x| {
         x.add_active_node(actv_probe.clone())
             .add_passive_node(pasv_probe.clone());
     });
+    let mock_self = hpv.self_peer().clone();
 
-    hpv.handle_disconnect(mock_self.clone(), &actv_probe);
+    hpv.handle_disconnect(&actv_probe, false);
+    dispatch(&mut hpv);
     pp.expect_msg(
         TIMEOUT,
         HpvMsg::Neighbour {
+            id: 0,
             peer: mock_self.clone(),
             prio: true,
         },
     );
+    // The candidate is only promoted once it accepts
+    assert!(hpv.passive_view.contains(&pasv_probe));
+    assert!(hpv.pending_neighbours.contains_key(&pasv_probe));
 
+    hpv.handle_neighbour_reply(0, pasv_probe.clone(), true);
     assert_eq!(hpv.passive_view.len(), 0);
     assert!(hpv.active_view.contains(&pasv_probe));
 }
@@ -37,23 +44,26 @@ fn promote_unprioritized_when_nonfinal_active_disconnects() {
     let (_, actv_probe1) = mock_hpv_peer();
     let (_, actv_probe2) = mock_hpv_peer();
     let (pp, pasv_probe) = mock_hpv_peer();
-    let (_, mock_self) = mock_hpv_peer();
 
-    let (_, mut hpv) = new_hyparview(|x| {
+    let mut hpv = new_hyparview(|x| {
         x.add_active_node(actv_probe1.clone())
             .add_active_node(actv_probe2.clone())
             .add_passive_node(pasv_probe.clone());
     });
+    let mock_self = hpv.self_peer().clone();
 
-    hpv.handle_disconnect(mock_self.clone(), &actv_probe1);
+    hpv.handle_disconnect(&actv_probe1, false);
+    dispatch(&mut hpv);
     pp.expect_msg(
         TIMEOUT,
         HpvMsg::Neighbour {
+            id: 0,
             peer: mock_self.clone(),
             prio: false,
         },
     );
 
+    hpv.handle_neighbour_reply(0, pasv_probe.clone(), true);
     assert_eq!(hpv.passive_view.len(), 0);
     assert!(hpv.active_view.contains(&pasv_probe));
     assert!(hpv.active_view.contains(&actv_probe2));
@@ -64,23 +74,31 @@ fn always_accept_prio_neighbour_requests() {
     let _ = System::new("test");
     let (ap, actv_probe) = mock_hpv_peer();
     let (np, neighbour_probe) = mock_hpv_peer();
-    let (_, mock_self) = mock_hpv_peer();
 
-    let (_, mut hpv) = new_hyparview(|x| {
+    let mut hpv = new_hyparview(|x| {
         x.add_active_node(actv_probe.clone()).change_config(|c| {
             c.max_active_view_size = 1;
         });
     });
+    let mock_self = hpv.self_peer().clone();
 
-    hpv.handle_neighbour(mock_self.clone(), neighbour_probe.clone(), true);
+    hpv.handle_neighbour(7, neighbour_probe.clone(), true);
+    dispatch(&mut hpv);
     np.expect_msg(
         TIMEOUT,
         HpvMsg::NeighbourReply {
+            id: 7,
             peer: mock_self.clone(),
             accepted: true,
         },
     );
-    ap.expect_msg(TIMEOUT, HpvMsg::Disconnect(mock_self.clone()));
+    ap.expect_msg(
+        TIMEOUT,
+        HpvMsg::Disconnect {
+            peer: mock_self.clone(),
+            alive: true,
+        },
+    );
     assert!(hpv.active_view.contains(&neighbour_probe));
 }
 
@@ -89,16 +107,18 @@ fn accept_nonprio_when_activeview_nonfull() {
     let _ = System::new("test");
     let (ap, actv_probe) = mock_hpv_peer();
     let (np, neighbour_probe) = mock_hpv_peer();
-    let (_, mock_self) = mock_hpv_peer();
 
-    let (_, mut hpv) = new_hyparview(|x| {
+    let mut hpv = new_hyparview(|x| {
         x.add_active_node(actv_probe.clone());
     });
+    let mock_self = hpv.self_peer().clone();
 
-    hpv.handle_neighbour(mock_self.clone(), neighbour_probe.clone(), false);
+    hpv.handle_neighbour(7, neighbour_probe.clone(), false);
+    dispatch(&mut hpv);
     np.expect_msg(
         TIMEOUT,
         HpvMsg::NeighbourReply {
+            id: 7,
             peer: mock_self.clone(),
             accepted: true,
         },
@@ -113,18 +133,20 @@ fn reject_nonprio_when_activeview_full() {
     let _ = System::new("test");
     let (ap, actv_probe) = mock_hpv_peer();
     let (np, neighbour_probe) = mock_hpv_peer();
-    let (_, mock_self) = mock_hpv_peer();
 
-    let (_, mut hpv) = new_hyparview(|x| {
+    let mut hpv = new_hyparview(|x| {
         x.add_active_node(actv_probe.clone()).change_config(|c| {
             c.max_active_view_size = 1;
         });
     });
+    let mock_self = hpv.self_peer().clone();
 
-    hpv.handle_neighbour(mock_self.clone(), neighbour_probe.clone(), false);
+    hpv.handle_neighbour(7, neighbour_probe.clone(), false);
+    dispatch(&mut hpv);
     np.expect_msg(
         TIMEOUT,
         HpvMsg::NeighbourReply {
+            id: 7,
             peer: mock_self.clone(),
             accepted: false,
         },
@@ -134,29 +156,347 @@ fn reject_nonprio_when_activeview_full() {
 }
 
 #[test]
-fn rejection_should_demote_requested_and_promote_another() {
+fn rejection_should_request_another_candidate() {
+    let _ = System::new("test");
+    let (_, actv_probe) = mock_hpv_peer();
+    let (rx1, cand_probe1) = mock_hpv_peer();
+    let (rx2, cand_probe2) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_active_node(actv_probe.clone())
+            .add_passive_node(cand_probe1.clone())
+            .add_passive_node(cand_probe2.clone())
+            .change_config(|c| c.max_active_view_size = 2);
+    });
+    let mock_self = hpv.self_peer().clone();
+
+    // Only one slot is free, so only one candidate is asked at a time
+    hpv.request_neighbours();
+    assert_eq!(hpv.pending_neighbours.len(), 1);
+    let ((_rp, rjct_probe), (cp, cand_probe)) = {
+        if hpv.pending_neighbours.contains_key(&cand_probe1) {
+            ((rx1, cand_probe1), (rx2, cand_probe2))
+        } else {
+            ((rx2, cand_probe2), (rx1, cand_probe1))
+        }
+    };
+    dispatch(&mut hpv);
+
+    hpv.handle_neighbour_reply(0, rjct_probe.clone(), false);
+    dispatch(&mut hpv);
+    cp.expect_msg(
+        TIMEOUT,
+        HpvMsg::Neighbour {
+            id: 1,
+            peer: mock_self.clone(),
+            prio: false,
+        },
+    );
+
+    assert!(hpv.active_view.contains(&actv_probe));
+    assert!(!hpv.active_view.contains(&cand_probe));
+    assert!(hpv.pending_neighbours.contains_key(&cand_probe));
+    assert!(hpv.passive_view.contains(&rjct_probe));
+}
+
+#[test]
+fn do_not_ask_declined_candidates_again() {
     let _ = System::new("test");
     let (_, actv_probe) = mock_hpv_peer();
-    let (_, rjct_probe) = mock_hpv_peer();
     let (cp, cand_probe) = mock_hpv_peer();
-    let (_, mock_self) = mock_hpv_peer();
 
-    let (_, mut hpv) = new_hyparview(|x| {
+    let mut hpv = new_hyparview(|x| {
         x.add_active_node(actv_probe.clone())
-            .add_active_node(rjct_probe.clone())
             .add_passive_node(cand_probe.clone());
     });
+    let mock_self = hpv.self_peer().clone();
 
-    hpv.handle_neighbour_reply(mock_self.clone(), rjct_probe.clone(), false);
+    hpv.request_neighbours();
+    dispatch(&mut hpv);
     cp.expect_msg(
         TIMEOUT,
         HpvMsg::Neighbour {
+            id: 0,
             peer: mock_self.clone(),
             prio: false,
         },
     );
 
-    assert!(hpv.active_view.contains(&actv_probe));
+    hpv.handle_neighbour_reply(0, cand_probe.clone(), false);
+    hpv.request_neighbours();
+    dispatch(&mut hpv);
+    cp.expect_no_msg(TIMEOUT);
+    assert!(hpv.pending_neighbours.is_empty());
+    assert!(hpv.passive_view.contains(&cand_probe));
+}
+
+#[test]
+fn forget_candidate_after_neighbour_timeout() {
+    let _ = System::new("test");
+    let (_, actv_probe) = mock_hpv_peer();
+    let (_, cand_probe1) = mock_hpv_peer();
+    let (_, cand_probe2) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_active_node(actv_probe.clone())
+            .add_passive_node(cand_probe1.clone())
+            .add_passive_node(cand_probe2.clone())
+            .change_config(|c| c.max_active_view_size = 2);
+    });
+
+    hpv.request_neighbours();
+    let (silent, (id, _)) = hpv.pending_neighbours
+        .iter()
+        .map(|(p, r)| (p.clone(), *r))
+        .next()
+        .unwrap();
+    let other = if silent == cand_probe1 {
+        cand_probe2
+    } else {
+        cand_probe1
+    };
+
+    hpv.handle_timer(Timer::NeighbourTimeout(id));
+    assert!(!hpv.passive_view.contains(&silent));
+    assert!(!hpv.pending_neighbours.contains_key(&silent));
+    assert!(hpv.pending_neighbours.contains_key(&other));
+}
+
+#[test]
+fn escalate_pending_requests_when_active_view_empties() {
+    let _ = System::new("test");
+    let (_, actv_probe1) = mock_hpv_peer();
+    let (_, actv_probe2) = mock_hpv_peer();
+    let (cp, cand_probe) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_active_node(actv_probe1.clone())
+            .add_active_node(actv_probe2.clone())
+            .add_passive_node(cand_probe.clone());
+    });
+    let mock_self = hpv.self_peer().clone();
+
+    hpv.request_neighbours();
+    hpv.handle_peer_failure(&actv_probe1);
+    hpv.handle_peer_failure(&actv_probe2);
+    dispatch(&mut hpv);
+
+    cp.expect_msg(
+        TIMEOUT,
+        HpvMsg::Neighbour {
+            id: 0,
+            peer: mock_self.clone(),
+            prio: false,
+        },
+    );
+    cp.expect_msg(
+        TIMEOUT,
+        HpvMsg::Neighbour {
+            id: 1,
+            peer: mock_self.clone(),
+            prio: true,
+        },
+    );
+    assert_eq!(hpv.pending_neighbours.get(&cand_probe).map(|r| r.1), Some(true));
+}
+
+#[test]
+fn accept_escalated_request_after_rejection_of_original() {
+    let _ = System::new("test");
+    let (_, actv_probe) = mock_hpv_peer();
+    let (_, cand_probe) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_active_node(actv_probe.clone())
+            .add_passive_node(cand_probe.clone());
+    });
+
+    hpv.request_neighbours();
+    hpv.handle_peer_failure(&actv_probe);
+    hpv.take_actions();
+    assert_eq!(hpv.pending_neighbours.get(&cand_probe), Some(&(1, true)));
+
+    // The candidate rejected the original request, but makes room for the prioritized one
+    hpv.handle(HpvMsg::NeighbourReply {
+        id: 0,
+        peer: cand_probe.clone(),
+        accepted: false,
+    });
+    assert!(hpv.pending_neighbours.contains_key(&cand_probe));
+    let actions = hpv.handle(HpvMsg::NeighbourReply {
+        id: 1,
+        peer: cand_probe.clone(),
+        accepted: true,
+    });
+
     assert!(hpv.active_view.contains(&cand_probe));
-    assert!(hpv.passive_view.contains(&rjct_probe));
+    assert!(!actions.iter().any(|action| match action {
+        Action::Send(_, HpvMsg::Disconnect { .. }) => true,
+        _ => false,
+    }));
+}
+
+#[test]
+fn release_slot_of_abandoned_neighbour_request() {
+    let _ = System::new("test");
+    let (np, neighbour_probe) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|_| {});
+    let mock_self = hpv.self_peer().clone();
+
+    hpv.handle_neighbour_reply(0, neighbour_probe.clone(), true);
+    dispatch(&mut hpv);
+
+    np.expect_msg(
+        TIMEOUT,
+        HpvMsg::Disconnect {
+            peer: mock_self,
+            alive: true,
+        },
+    );
+    assert!(!hpv.active_view.contains(&neighbour_probe));
+}
+
+#[test]
+fn keep_evicting_peer_as_passive() {
+    let _ = System::new("test");
+    let (_, actv_probe1) = mock_hpv_peer();
+    let (_, actv_probe2) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_active_node(actv_probe1.clone())
+            .add_active_node(actv_probe2.clone());
+    });
+
+    hpv.handle(HpvMsg::Disconnect {
+        peer: actv_probe1.clone(),
+        alive: true,
+    });
+
+    assert!(!hpv.active_view.contains(&actv_probe1));
+    assert!(hpv.passive_view.contains(&actv_probe1));
+    assert!(hpv.active_view.contains(&actv_probe2));
+}
+
+#[test]
+fn forget_leaving_peer() {
+    let _ = System::new("test");
+    let (_, actv_probe1) = mock_hpv_peer();
+    let (_, actv_probe2) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_active_node(actv_probe1.clone())
+            .add_active_node(actv_probe2.clone());
+    });
+
+    hpv.handle(HpvMsg::Disconnect {
+        peer: actv_probe1.clone(),
+        alive: false,
+    });
+
+    assert!(!hpv.active_view.contains(&actv_probe1));
+    assert!(!hpv.passive_view.contains(&actv_probe1));
+    assert!(hpv.active_view.contains(&actv_probe2));
+}
+
+#[test]
+fn demote_surplus_peers_when_active_view_shrinks() {
+    let _ = System::new("test");
+    let (ap1, actv_probe1) = mock_hpv_peer();
+    let (ap2, actv_probe2) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_active_node(actv_probe1.clone())
+            .add_active_node(actv_probe2.clone());
+    });
+    let mock_self = hpv.self_peer().clone();
+
+    hpv.change_config(|c| c.max_active_view_size = 1);
+    dispatch(&mut hpv);
+
+    assert_eq!(hpv.active_view.len(), 1);
+    assert_eq!(hpv.passive_view.len(), 1);
+    let demoted = hpv.passive_view.iter().next().unwrap().clone();
+    let receiver = if demoted == actv_probe1 { ap1 } else { ap2 };
+    receiver.expect_msg(
+        TIMEOUT,
+        HpvMsg::Disconnect {
+            peer: mock_self,
+            alive: true,
+        },
+    );
+}
+
+#[test]
+fn disconnect_demoted_peers_with_config_change() {
+    let _ = System::new("test");
+    let (_, actv_probe1) = mock_hpv_peer();
+    let (_, actv_probe2) = mock_hpv_peer();
+    let (_, actv_probe3) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_active_node(actv_probe1.clone())
+            .add_active_node(actv_probe2.clone())
+            .add_active_node(actv_probe3.clone());
+    });
+    let mock_self = hpv.self_peer().clone();
+    let mut config = hpv.config().clone();
+    config.max_active_view_size = 2;
+    config.active_eviction = EvictionPolicy::OldestFirst;
+
+    let actions = hpv.set_config(config).unwrap();
+
+    assert!(actions.contains(&Action::Send(
+        actv_probe1.addr,
+        HpvMsg::Disconnect {
+            peer: mock_self,
+            alive: true,
+        },
+    )));
+    assert!(hpv.passive_view.contains(&actv_probe1));
+}
+
+#[test]
+fn disconnect_demoted_peers_as_soon_as_running_actor_is_reconfigured() {
+    let _ = System::new("test");
+    let (ap1, actv_probe1) = mock_hpv_peer();
+    let (_, actv_probe2) = mock_hpv_peer();
+    let (_, actv_probe3) = mock_hpv_peer();
+
+    let (_, self_peer, addr) = start_hyparview(|x| {
+        x.add_active_node(actv_probe1.clone())
+            .add_active_node(actv_probe2.clone())
+            .add_active_node(actv_probe3.clone());
+    });
+    let mut config = Config::default();
+    config.max_active_view_size = 2;
+    config.active_eviction = EvictionPolicy::OldestFirst;
+
+    addr.do_send(SetConfig(config));
+
+    ap1.expect_msg(
+        TIMEOUT,
+        HpvMsg::Disconnect {
+            peer: self_peer,
+            alive: true,
+        },
+    );
+}
+
+#[test]
+fn forget_surplus_peers_when_passive_view_shrinks() {
+    let _ = System::new("test");
+    let (_, pasv_probe1) = mock_hpv_peer();
+    let (_, pasv_probe2) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_passive_node(pasv_probe1.clone())
+            .add_passive_node(pasv_probe2.clone());
+    });
+
+    hpv.change_config(|c| c.max_passive_view_size = 1);
+
+    // The default policy forgets the least recently used peer
+    assert_eq!(hpv.passive_view.len(), 1);
+    assert!(hpv.passive_view.contains(&pasv_probe2));
 }
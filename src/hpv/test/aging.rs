@@ -0,0 +1,160 @@
+extern crate actix;
+extern crate futures;
+extern crate futures_channel;
+
+use self::actix::prelude::*;
+use super::*;
+use hpv::{Action, HpvMsg, Timer};
+use std::collections::HashSet;
+
+const PROBE_INTERVAL: Duration = TIMEOUT;
+
+#[test]
+fn evict_oldest_passive_peer() {
+    let _ = System::new("test");
+    let (_, old_peer) = mock_hpv_peer();
+    let (_, fresh_peer) = mock_hpv_peer();
+    let (_, new_peer) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_passive_node(old_peer.clone())
+            .add_passive_node(fresh_peer.clone())
+            .change_config(|c| c.max_passive_view_size = 2);
+    });
+
+    hpv.age_passive_view();
+    hpv.handle(HpvMsg::Heartbeat(fresh_peer.clone()));
+    hpv.add_node_to_passive_view(new_peer.clone());
+
+    assert!(!hpv.passive_view.contains(&old_peer));
+    assert!(hpv.passive_view.contains(&fresh_peer));
+    assert!(hpv.passive_view.contains(&new_peer));
+}
+
+#[test]
+fn merge_shuffle_reply_dropping_oldest_peers_first() {
+    let _ = System::new("test");
+    let (_, old_peer) = mock_hpv_peer();
+    let (_, fresh_peer) = mock_hpv_peer();
+    let (_, received_peer) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_passive_node(old_peer.clone())
+            .add_passive_node(fresh_peer.clone())
+            .add_outstanding_shuffle(1337, HashSet::default())
+            .change_config(|c| c.max_passive_view_size = 2);
+    });
+
+    hpv.age_passive_view();
+    hpv.handle(HpvMsg::Heartbeat(fresh_peer.clone()));
+    hpv.handle_shuffle_reply(1337, hashset!{received_peer.clone()});
+
+    assert!(!hpv.passive_view.contains(&old_peer));
+    assert!(hpv.passive_view.contains(&fresh_peer));
+    assert!(hpv.passive_view.contains(&received_peer));
+}
+
+#[test]
+fn probe_only_when_configured() {
+    let _ = System::new("test");
+
+    let mut hpv = new_hyparview(|_| {});
+    assert!(!hpv.start().iter().any(|action| match action {
+        Action::Schedule(Timer::Probe, _) => true,
+        _ => false,
+    }));
+
+    let mut hpv = new_hyparview(|x| {
+        x.change_config(|c| c.passive_probe_interval = Some(PROBE_INTERVAL));
+    });
+    assert!(
+        hpv.start()
+            .contains(&Action::Schedule(Timer::Probe, PROBE_INTERVAL))
+    );
+    assert_eq!(
+        hpv.handle_timer(Timer::Probe),
+        vec![Action::Schedule(Timer::Probe, PROBE_INTERVAL)]
+    );
+}
+
+#[test]
+fn probe_oldest_passive_peer() {
+    let _ = System::new("test");
+    let (_, old_peer) = mock_hpv_peer();
+    let (_, fresh_peer) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_passive_node(old_peer.clone())
+            .add_passive_node(fresh_peer.clone());
+    });
+    let mock_self = hpv.self_peer().clone();
+
+    hpv.age_passive_view();
+    hpv.handle(HpvMsg::Heartbeat(fresh_peer.clone()));
+    hpv.probe_passive_view();
+
+    assert_eq!(
+        hpv.take_actions(),
+        vec![Action::Send(old_peer.addr, HpvMsg::Probe(mock_self))]
+    );
+}
+
+#[test]
+fn drop_passive_peer_that_ignores_probe() {
+    let _ = System::new("test");
+    let (_, silent_peer) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_passive_node(silent_peer.clone());
+    });
+
+    hpv.probe_passive_view();
+    hpv.probe_passive_view();
+
+    assert!(!hpv.passive_view.contains(&silent_peer));
+}
+
+#[test]
+fn keep_passive_peer_that_answers_probe() {
+    let _ = System::new("test");
+    let (_, live_peer) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_passive_node(live_peer.clone());
+    });
+
+    hpv.probe_passive_view();
+    hpv.handle(HpvMsg::ProbeReply(live_peer.clone()));
+    hpv.probe_passive_view();
+
+    assert!(hpv.passive_view.contains(&live_peer));
+}
+
+#[test]
+fn drop_unreachable_probed_peer() {
+    let _ = System::new("test");
+    let (_, dead_peer) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_passive_node(dead_peer.clone());
+    });
+
+    hpv.probe_passive_view();
+    hpv.handle_unreachable(dead_peer.addr);
+
+    assert!(!hpv.passive_view.contains(&dead_peer));
+}
+
+#[test]
+fn answer_probe() {
+    let _ = System::new("test");
+    let (pp, prober) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|_| {});
+    let mock_self = hpv.self_peer().clone();
+
+    let actions = hpv.handle(HpvMsg::Probe(prober));
+    dispatch_actions(actions);
+
+    pp.expect_msg(TIMEOUT, HpvMsg::ProbeReply(mock_self));
+}
@@ -0,0 +1,106 @@
+extern crate actix;
+extern crate futures;
+extern crate futures_channel;
+
+use self::actix::prelude::*;
+use super::*;
+use hpv::{Action, Event, HpvMsg, Timer};
+use std::time::Duration;
+
+const SEED_BACKOFF: Duration = Duration::from_millis(100);
+
+#[test]
+fn join_through_seeds_at_startup() {
+    let _ = System::new("test");
+    let (sp1, seed1) = mock_hpv_peer();
+    let (sp2, seed2) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.change_config(|c| c.seed_addrs = vec![seed1.addr, seed2.addr]);
+    });
+    let mock_self = hpv.self_peer().clone();
+
+    dispatch_actions(hpv.start());
+
+    // Only one of the seeds is tried at a time
+    let first = sp1.recv_timeout(TIMEOUT).ok();
+    let second = sp2.recv_timeout(TIMEOUT).ok();
+    assert!(first.is_some() != second.is_some());
+    assert_eq!(first.or(second), Some(HpvMsg::Join(mock_self)));
+}
+
+#[test]
+fn skip_own_address_among_seeds() {
+    let _ = System::new("test");
+    let mut hpv = new_hyparview(|_| {});
+    let self_addr = hpv.self_peer().addr;
+    hpv.change_config(|c| c.seed_addrs = vec![self_addr]);
+
+    let actions = hpv.start();
+    assert!(actions.iter().all(|a| match a {
+        Action::Send(_, _) => false,
+        _ => true,
+    }));
+}
+
+#[test]
+fn back_off_between_seed_rounds() {
+    let _ = System::new("test");
+    let (sp, seed) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.change_config(|c| {
+            c.seed_addrs = vec![seed.addr];
+            c.seed_backoff = SEED_BACKOFF;
+        });
+    });
+    let mock_self = hpv.self_peer().clone();
+
+    let attempt = join_attempt(&hpv.start());
+    let actions = hpv.handle_timer(Timer::JoinTimeout(attempt));
+    assert!(actions.contains(&Action::Emit(Event::JoinFailed)));
+    assert!(actions.contains(&Action::Schedule(Timer::SeedRetry, SEED_BACKOFF)));
+
+    let actions = hpv.handle_timer(Timer::SeedRetry);
+    let attempt = join_attempt(&actions);
+    dispatch_actions(actions);
+    sp.expect_msg(TIMEOUT, HpvMsg::Join(mock_self));
+
+    let actions = hpv.handle_timer(Timer::JoinTimeout(attempt));
+    assert!(actions.contains(&Action::Schedule(Timer::SeedRetry, SEED_BACKOFF * 2)));
+}
+
+#[test]
+fn rejoin_through_seeds_when_views_run_empty() {
+    let _ = System::new("test");
+    let (_, actv_probe) = mock_hpv_peer();
+    let (sp, seed) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_active_node(actv_probe.clone())
+            .change_config(|c| c.seed_addrs = vec![seed.addr]);
+    });
+    let mock_self = hpv.self_peer().clone();
+
+    dispatch_actions(hpv.start());
+    sp.expect_no_msg(TIMEOUT);
+
+    hpv.handle_peer_failure(&actv_probe);
+    dispatch(&mut hpv);
+    sp.expect_msg(TIMEOUT, HpvMsg::Join(mock_self));
+}
+
+#[test]
+fn do_not_rejoin_after_leave() {
+    let _ = System::new("test");
+    let (_, actv_probe) = mock_hpv_peer();
+    let (sp, seed) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_active_node(actv_probe.clone())
+            .change_config(|c| c.seed_addrs = vec![seed.addr]);
+    });
+
+    dispatch_actions(hpv.leave(false));
+    sp.expect_no_msg(TIMEOUT);
+}
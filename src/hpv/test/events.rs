@@ -0,0 +1,161 @@
+extern crate actix;
+extern crate futures;
+extern crate futures_channel;
+
+use self::actix::prelude::*;
+use self::futures::{Future, Stream};
+use super::*;
+use hpv::{Event, HpvMsg, SubscribeStream};
+
+#[test]
+fn report_joining_peer() {
+    let _ = System::new("test");
+    let (_, joiner) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|_| {});
+
+    hpv.handle_join(joiner.clone());
+    assert_eq!(
+        dispatch_events(&mut hpv),
+        vec![
+            Event::PeerDiscovered(joiner.clone()),
+            Event::ActivePeerAdded(joiner.clone()),
+        ]
+    );
+}
+
+#[test]
+fn report_demotion_to_passive_view() {
+    let _ = System::new("test");
+    let (_, actv_peer) = mock_hpv_peer();
+    let (_, joiner) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_active_node(actv_peer.clone())
+            .change_config(|c| c.max_active_view_size = 1);
+    });
+    dispatch_events(&mut hpv);
+
+    hpv.handle_join(joiner.clone());
+    assert_eq!(
+        dispatch_events(&mut hpv),
+        vec![
+            Event::PeerDiscovered(joiner.clone()),
+            Event::ActivePeerRemoved(actv_peer.clone()),
+            Event::ActivePeerAdded(joiner.clone()),
+            Event::PassivePeerAdded(actv_peer.clone()),
+        ]
+    );
+}
+
+#[test]
+fn report_passive_eviction() {
+    let _ = System::new("test");
+    let (_, offered_peer) = mock_hpv_peer();
+    let (_, received_peer) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_passive_node(offered_peer.clone())
+            .add_outstanding_shuffle(0, hashset!{offered_peer.clone()})
+            .change_config(|c| c.max_passive_view_size = 1);
+    });
+    dispatch_events(&mut hpv);
+
+    hpv.handle_shuffle_reply(0, hashset!{received_peer.clone()});
+    assert_eq!(
+        dispatch_events(&mut hpv),
+        vec![
+            Event::PeerDiscovered(received_peer.clone()),
+            Event::PassivePeerEvicted(offered_peer.clone()),
+            Event::PassivePeerAdded(received_peer.clone()),
+        ]
+    );
+}
+
+#[test]
+fn report_completed_join() {
+    let _ = System::new("test");
+    let (_, contact) = mock_hpv_peer();
+    let (_, acceptor) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|_| {});
+
+    hpv.handle_init_join(vec![contact.addr]);
+    assert_eq!(dispatch_events(&mut hpv), vec![]);
+
+    // A peer that the contact forwarded the join to acknowledged it before the contact did
+    assert_eq!(
+        dispatch_actions(hpv.handle(HpvMsg::JoinReply(acceptor.clone()))),
+        vec![
+            Event::PeerDiscovered(acceptor.clone()),
+            Event::ActivePeerAdded(acceptor.clone()),
+            Event::JoinCompleted(acceptor.clone()),
+        ]
+    );
+
+    assert_eq!(
+        dispatch_actions(hpv.handle(HpvMsg::JoinReply(contact.clone()))),
+        vec![
+            Event::PeerDiscovered(contact.clone()),
+            Event::ActivePeerAdded(contact.clone()),
+        ]
+    );
+}
+
+#[test]
+fn await_acknowledgement_of_join_despite_active_peers() {
+    let _ = System::new("test");
+    let (_, actv_peer) = mock_hpv_peer();
+    let (_, contact) = mock_hpv_peer();
+    let (_, neighbour) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_active_node(actv_peer.clone());
+    });
+    dispatch_events(&mut hpv);
+
+    hpv.handle_init_join(vec![contact.addr]);
+    assert_eq!(dispatch_events(&mut hpv), vec![]);
+
+    // A new neighbour that knows nothing of the join does not complete it
+    let actions = hpv.handle(HpvMsg::Neighbour {
+        id: 0,
+        peer: neighbour.clone(),
+        prio: true,
+    });
+    assert!(!dispatch_actions(actions).contains(&Event::JoinCompleted(neighbour.clone())));
+
+    let actions = hpv.handle(HpvMsg::JoinReply(contact.clone()));
+    assert!(dispatch_actions(actions).contains(&Event::JoinCompleted(contact.clone())));
+}
+
+#[test]
+fn deliver_events_to_subscribers() {
+    let _ = System::new("test");
+    let (_, joiner) = mock_hpv_peer();
+
+    let (events, _, addr) = start_hyparview(|_| {});
+
+    addr.do_send(HpvMsg::Join(joiner.clone()));
+    events.expect_msg(TIMEOUT, Event::PeerDiscovered(joiner.clone()));
+    events.expect_msg(TIMEOUT, Event::ActivePeerAdded(joiner.clone()));
+}
+
+#[test]
+fn stream_events() {
+    let _ = System::new("test");
+    let (_, joiner) = mock_hpv_peer();
+
+    let (_, _, addr) = start_hyparview(|_| {});
+    let stream = addr.send(SubscribeStream).wait().unwrap();
+
+    addr.do_send(HpvMsg::Join(joiner.clone()));
+    let received: Vec<Event> = stream.take(2).collect().wait().unwrap();
+    assert_eq!(
+        received,
+        vec![
+            Event::PeerDiscovered(joiner.clone()),
+            Event::ActivePeerAdded(joiner.clone()),
+        ]
+    );
+}
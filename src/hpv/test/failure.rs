@@ -0,0 +1,150 @@
+extern crate actix;
+extern crate futures;
+extern crate futures_channel;
+
+use self::actix::prelude::*;
+use super::*;
+use hpv::{Dropped, HpvMsg, HyParViewActor, Inspect, Outbound, Peer, PeerId, Views};
+use std::io;
+use std::io::ErrorKind::WouldBlock;
+use std::thread;
+
+/// Gives up on every message, as a transport would on a slow peer
+struct DroppingTransport;
+
+impl Actor for DroppingTransport {
+    type Context = Context<Self>;
+}
+
+impl Handler<Outbound> for DroppingTransport {
+    type Result = Result<(), io::Error>;
+
+    fn handle(&mut self, msg: Outbound, _ctx: &mut Context<Self>) -> Self::Result {
+        Err(io::Error::new(WouldBlock, Dropped::QueueFull(msg.to)))
+    }
+}
+
+#[test]
+fn send_heartbeats_to_active_view() {
+    let _ = System::new("test");
+    let (ap, actv_probe) = mock_hpv_peer();
+    let (pp, pasv_probe) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_active_node(actv_probe.clone())
+            .add_passive_node(pasv_probe.clone());
+    });
+    let mock_self = hpv.self_peer().clone();
+
+    hpv.check_heartbeats();
+    dispatch(&mut hpv);
+
+    ap.expect_msg(TIMEOUT, HpvMsg::Heartbeat(mock_self));
+    pp.expect_no_msg(TIMEOUT);
+}
+
+#[test]
+fn replace_silent_active_peer() {
+    let _ = System::new("test");
+    let (_, actv_probe) = mock_hpv_peer();
+    let (pp, pasv_probe) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_active_node(actv_probe.clone())
+            .add_passive_node(pasv_probe.clone())
+            .change_config(|c| c.max_missed_heartbeats = 1);
+    });
+    let mock_self = hpv.self_peer().clone();
+
+    hpv.check_heartbeats();
+    hpv.check_heartbeats();
+    dispatch(&mut hpv);
+
+    pp.expect_msg(
+        TIMEOUT,
+        HpvMsg::Neighbour {
+            id: 0,
+            peer: mock_self,
+            prio: true,
+        },
+    );
+    assert!(!hpv.active_view.contains(&actv_probe));
+    assert!(!hpv.passive_view.contains(&actv_probe));
+
+    hpv.handle_neighbour_reply(0, pasv_probe.clone(), true);
+    assert!(hpv.active_view.contains(&pasv_probe));
+}
+
+#[test]
+fn heartbeats_keep_active_peer() {
+    let _ = System::new("test");
+    let (_, actv_probe) = mock_hpv_peer();
+    let (pp, pasv_probe) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_active_node(actv_probe.clone())
+            .add_passive_node(pasv_probe.clone())
+            .change_config(|c| c.max_missed_heartbeats = 1);
+    });
+
+    hpv.check_heartbeats();
+    hpv.handle_heartbeat(&actv_probe);
+    hpv.check_heartbeats();
+    dispatch(&mut hpv);
+
+    pp.expect_no_msg(TIMEOUT);
+    assert!(hpv.active_view.contains(&actv_probe));
+}
+
+#[test]
+fn replace_unreachable_active_peer() {
+    let _ = System::new("test");
+    let (_, actv_probe1) = mock_hpv_peer();
+    let (_, actv_probe2) = mock_hpv_peer();
+    let (pp, pasv_probe) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_active_node(actv_probe1.clone())
+            .add_active_node(actv_probe2.clone())
+            .add_passive_node(pasv_probe.clone());
+    });
+    let mock_self = hpv.self_peer().clone();
+
+    hpv.handle_unreachable(actv_probe1.addr);
+    dispatch(&mut hpv);
+
+    pp.expect_msg(
+        TIMEOUT,
+        HpvMsg::Neighbour {
+            id: 0,
+            peer: mock_self,
+            prio: false,
+        },
+    );
+    assert!(!hpv.active_view.contains(&actv_probe1));
+    assert!(hpv.active_view.contains(&actv_probe2));
+
+    hpv.handle_neighbour_reply(0, pasv_probe.clone(), true);
+    assert!(hpv.active_view.contains(&pasv_probe));
+}
+
+#[test]
+fn keep_active_peer_whose_messages_are_dropped() {
+    let _ = System::new("test");
+    let (_, actv_probe) = mock_hpv_peer();
+    let transport = Arbiter::start(|_| DroppingTransport);
+    let mut hpv = HyParViewActor::new(
+        Peer::new(PeerId::random(), mock_addr()),
+        transport.recipient(),
+    );
+    hpv.protocol().add_active_node(actv_probe.clone());
+    let addr = Arbiter::start(|_| hpv);
+
+    // The reply to the probe is dropped, which must not count as a failure of the peer
+    addr.do_send(HpvMsg::Probe(actv_probe.clone()));
+    thread::sleep(TIMEOUT);
+    let (rx, view_recipient): (Receiver<Views>, Recipient<Views>) = mock_recipient();
+    addr.do_send(Inspect(view_recipient));
+
+    assert!(rx.recv_msg(TIMEOUT).active_view.contains(&actv_probe));
+}
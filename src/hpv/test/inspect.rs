@@ -4,14 +4,15 @@ extern crate futures_channel;
 
 use self::actix::prelude::*;
 use super::*;
-use hpv::{BoundedSet, HpvMsg, Views};
+use bounded_set::BoundedSet;
+use hpv::{Inspect, Views};
 
 #[test]
 fn allow_inspections() {
     let _ = System::new("test");
-    let (_, addr) = start_hyparview(|_| {});
+    let (_, _, addr) = start_hyparview(|_| {});
     let (rx, view_recipient): (Receiver<Views>, Recipient<Views>) = mock_recipient();
-    let _req = addr.send(HpvMsg::Inspect(view_recipient));
+    let _req = addr.send(Inspect(view_recipient));
 
     rx.expect_msg(
         TIMEOUT,
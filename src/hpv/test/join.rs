@@ -3,16 +3,18 @@ extern crate futures;
 extern crate futures_channel;
 
 use self::actix::prelude::*;
+use self::futures::Future;
 use super::*;
-use hpv::HpvMsg;
+use hpv::{Action, Event, HpvMsg, InitiateJoin, JoinResult, Timer};
 
 #[test]
 fn initiate_join() {
     let _ = System::new("test");
-    let (_, mut hpv) = new_hyparview(|_| {});
+    let mut hpv = new_hyparview(|_| {});
     let (rx, bootstrap) = mock_hpv_peer();
-    let (_, mock_self) = mock_hpv_peer();
-    hpv.handle_init_join(mock_self.clone(), bootstrap);
+    let mock_self = hpv.self_peer().clone();
+    hpv.handle_init_join(vec![bootstrap.addr]);
+    dispatch(&mut hpv);
     rx.expect_msg(TIMEOUT, HpvMsg::Join(mock_self));
 }
 
@@ -23,15 +25,16 @@ fn broadcast_to_active_view() {
     let (ap2, actv_probe2) = mock_hpv_peer();
     let (pp1, pasv_probe1) = mock_hpv_peer();
     let (_, join_probe) = mock_hpv_peer();
-    let (_, mock_self) = mock_hpv_peer();
 
-    let (_, mut hpv) = new_hyparview(|x| {
+    let mut hpv = new_hyparview(|x| {
         x.add_active_node(actv_probe1.clone())
             .add_active_node(actv_probe2.clone())
             .add_passive_node(pasv_probe1.clone());
     });
+    let mock_self = hpv.self_peer().clone();
 
-    hpv.handle_join(mock_self.clone(), join_probe.clone());
+    hpv.handle_join(join_probe.clone());
+    dispatch(&mut hpv);
 
     let forward_join = HpvMsg::ForwardJoin {
         joining: join_probe.clone(),
@@ -50,9 +53,8 @@ fn make_room_for_joins() {
     let (ap2, actv_probe2) = mock_hpv_peer();
     let (pp1, pasv_probe) = mock_hpv_peer();
     let (_, join_probe) = mock_hpv_peer();
-    let (_, mock_self) = mock_hpv_peer();
 
-    let (_, mut hpv) = new_hyparview(|x| {
+    let mut hpv = new_hyparview(|x| {
         x.add_active_node(actv_probe1.clone())
             .add_active_node(actv_probe2.clone())
             .add_passive_node(pasv_probe.clone())
@@ -60,8 +62,10 @@ fn make_room_for_joins() {
                 c.max_active_view_size = 2;
             });
     });
+    let mock_self = hpv.self_peer().clone();
 
-    hpv.handle_join(mock_self.clone(), join_probe.clone());
+    hpv.handle_join(join_probe.clone());
+    dispatch(&mut hpv);
 
     pp1.expect_no_msg(TIMEOUT);
 
@@ -80,7 +84,13 @@ fn make_room_for_joins() {
     assert!(hpv.passive_view.contains(&dead_probe));
     assert!(hpv.passive_view.contains(&pasv_probe));
 
-    dead_rcv.expect_msg(TIMEOUT, HpvMsg::Disconnect(mock_self.clone()));
+    dead_rcv.expect_msg(
+        TIMEOUT,
+        HpvMsg::Disconnect {
+            peer: mock_self.clone(),
+            alive: true,
+        },
+    );
     live_rcv.expect_msg(
         TIMEOUT,
         HpvMsg::ForwardJoin {
@@ -96,26 +106,45 @@ fn include_joiner_at_ttl0() {
     let _ = System::new("test");
     let (_, actv_probe) = mock_hpv_peer();
     let (_, join_probe) = mock_hpv_peer();
-    let (_, mock_self) = mock_hpv_peer();
-    let (_, mut hpv) = new_hyparview(|x| {
+    let mut hpv = new_hyparview(|x| {
         x.add_active_node(actv_probe.clone()).change_config(|c| {
             c.max_active_view_size = 1;
         });
     });
 
-    hpv.handle_forward_join(mock_self.clone(), join_probe.clone(), join_probe.clone(), 0);
+    hpv.handle_forward_join(join_probe.clone(), join_probe.clone(), 0);
+    dispatch(&mut hpv);
 
     assert!(hpv.active_view.contains(&join_probe));
 }
 
+#[test]
+fn acknowledge_forwarded_join() {
+    let _ = System::new("test");
+    let (_, actv_probe) = mock_hpv_peer();
+    let (_, join_probe) = mock_hpv_peer();
+    let mut hpv = new_hyparview(|x| {
+        x.add_active_node(actv_probe.clone());
+    });
+    let mock_self = hpv.self_peer().clone();
+
+    hpv.handle_forward_join(join_probe.clone(), actv_probe.clone(), 0);
+
+    assert!(hpv.active_view.contains(&actv_probe));
+    assert!(
+        hpv.take_actions()
+            .contains(&Action::Send(join_probe.addr, HpvMsg::JoinReply(mock_self)))
+    );
+}
+
 #[test]
 fn include_joiner_when_active_view_empty() {
     let _ = System::new("test");
     let (_, join_probe) = mock_hpv_peer();
-    let (_, mock_self) = mock_hpv_peer();
-    let (_, mut hpv) = new_hyparview(|_| {});
+    let mut hpv = new_hyparview(|_| {});
 
-    hpv.handle_forward_join(mock_self.clone(), join_probe.clone(), join_probe.clone(), 1);
+    hpv.handle_forward_join(join_probe.clone(), join_probe.clone(), 1);
+    dispatch(&mut hpv);
 
     assert!(hpv.active_view.contains(&join_probe));
 }
@@ -126,20 +155,20 @@ fn continue_forwarding_until_ttl0() {
     let (fp, forw_probe) = mock_hpv_peer();
     let (_, actv_probe) = mock_hpv_peer();
     let (_, join_probe) = mock_hpv_peer();
-    let (_, mock_self) = mock_hpv_peer();
-    let (_, mut hpv) = new_hyparview(|x| {
+    let mut hpv = new_hyparview(|x| {
         // non-empty active view
         x.add_active_node(forw_probe.clone())
             .add_active_node(actv_probe.clone());
     });
+    let mock_self = hpv.self_peer().clone();
 
     // Don't include the node of a ForwardJoin when TTL != passive_rwl
     hpv.handle_forward_join(
-        mock_self.clone(),
         join_probe.clone(),
         actv_probe.clone(),
         Config::default().passive_rwl + 1,
     );
+    dispatch(&mut hpv);
     fp.expect_msg(
         TIMEOUT,
         HpvMsg::ForwardJoin {
@@ -152,11 +181,11 @@ fn continue_forwarding_until_ttl0() {
 
     // Include the node of a ForwardJoin when TTL == passive_rwl
     hpv.handle_forward_join(
-        mock_self.clone(),
         join_probe.clone(),
         actv_probe.clone(),
         Config::default().passive_rwl,
     );
+    dispatch(&mut hpv);
     fp.expect_msg(
         TIMEOUT,
         HpvMsg::ForwardJoin {
@@ -167,3 +196,89 @@ fn continue_forwarding_until_ttl0() {
     );
     assert!(hpv.passive_view.contains(&join_probe));
 }
+
+#[test]
+fn acknowledge_join() {
+    let _ = System::new("test");
+    let (jp, join_probe) = mock_hpv_peer();
+    let mut hpv = new_hyparview(|_| {});
+    let mock_self = hpv.self_peer().clone();
+
+    hpv.handle_join(join_probe.clone());
+    dispatch(&mut hpv);
+
+    jp.expect_msg(TIMEOUT, HpvMsg::JoinReply(mock_self));
+}
+
+#[test]
+fn complete_join_on_acknowledgement() {
+    let _ = System::new("test");
+    let (_, contact) = mock_hpv_peer();
+    let mut hpv = new_hyparview(|_| {});
+
+    hpv.handle_init_join(vec![contact.addr]);
+    dispatch(&mut hpv);
+    hpv.handle_join_reply(contact.clone());
+
+    assert!(dispatch_events(&mut hpv).contains(&Event::JoinCompleted(contact.clone())));
+    assert!(hpv.active_view.contains(&contact));
+}
+
+#[test]
+fn try_next_contact_after_join_timeout() {
+    let _ = System::new("test");
+    let (_, silent_contact) = mock_hpv_peer();
+    let (cp, contact) = mock_hpv_peer();
+    let mut hpv = new_hyparview(|_| {});
+    let mock_self = hpv.self_peer().clone();
+
+    let actions = hpv.join(vec![silent_contact.addr, contact.addr]);
+    let attempt = join_attempt(&actions);
+    dispatch_actions(actions);
+    cp.expect_no_msg(TIMEOUT);
+
+    dispatch_actions(hpv.handle_timer(Timer::JoinTimeout(attempt)));
+    cp.expect_msg(TIMEOUT, HpvMsg::Join(mock_self));
+}
+
+#[test]
+fn fail_join_when_no_contact_acknowledges() {
+    let _ = System::new("test");
+    let (_, contact) = mock_hpv_peer();
+    let mut hpv = new_hyparview(|_| {});
+
+    let attempt = join_attempt(&hpv.join(vec![contact.addr]));
+    let events = dispatch_actions(hpv.handle_timer(Timer::JoinTimeout(attempt)));
+
+    assert_eq!(events, vec![Event::JoinFailed]);
+    // A late timeout of the same attempt is ignored
+    assert!(hpv.handle_timer(Timer::JoinTimeout(attempt)).is_empty());
+}
+
+#[test]
+fn return_join_result_to_requester() {
+    let _ = System::new("test");
+    let (cp, contact) = mock_hpv_peer();
+    let (_, self_peer, addr) = start_hyparview(|_| {});
+
+    let join = addr.send(InitiateJoin(vec![contact.addr]));
+    cp.expect_msg(TIMEOUT, HpvMsg::Join(self_peer));
+    addr.do_send(HpvMsg::JoinReply(contact.clone()));
+
+    assert_eq!(join.wait().unwrap().unwrap(), JoinResult::JoinCompleted(contact));
+}
+
+#[test]
+fn skip_unreachable_contacts() {
+    let _ = System::new("test");
+    // Not registered with the network, so every message to it fails
+    let dead_contact = mock_addr();
+    let (cp, contact) = mock_hpv_peer();
+    let (_, self_peer, addr) = start_hyparview(|_| {});
+
+    let join = addr.send(InitiateJoin(vec![dead_contact, contact.addr]));
+    cp.expect_msg(TIMEOUT * 10, HpvMsg::Join(self_peer));
+    addr.do_send(HpvMsg::JoinReply(contact.clone()));
+
+    assert_eq!(join.wait().unwrap().unwrap(), JoinResult::JoinCompleted(contact));
+}
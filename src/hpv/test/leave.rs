@@ -0,0 +1,99 @@
+extern crate actix;
+extern crate futures;
+extern crate futures_channel;
+
+use self::actix::prelude::*;
+use super::*;
+use hpv::{HpvMsg, Inspect, Leave, Views};
+
+#[test]
+fn notify_active_peers_on_leave() {
+    let _ = System::new("test");
+    let (ap, actv_probe) = mock_hpv_peer();
+    let (pp, pasv_probe) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_active_node(actv_probe.clone())
+            .add_passive_node(pasv_probe.clone());
+    });
+    let mock_self = hpv.self_peer().clone();
+
+    dispatch_actions(hpv.leave(false));
+
+    ap.expect_msg(
+        TIMEOUT,
+        HpvMsg::Disconnect {
+            peer: mock_self,
+            alive: false,
+        },
+    );
+    pp.expect_no_msg(TIMEOUT);
+    assert_eq!(hpv.active_view.len(), 0);
+    assert_eq!(hpv.passive_view.len(), 0);
+}
+
+#[test]
+fn hand_off_passive_peers_on_leave() {
+    let _ = System::new("test");
+    let (ap, actv_probe) = mock_hpv_peer();
+    let (_, pasv_probe) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_active_node(actv_probe.clone())
+            .add_passive_node(pasv_probe.clone());
+    });
+    let mock_self = hpv.self_peer().clone();
+
+    dispatch_actions(hpv.leave(true));
+
+    // The handoff precedes the disconnect, so its peers can replace us
+    ap.expect_msg(TIMEOUT, HpvMsg::Handoff(hashset!{pasv_probe.clone()}));
+    ap.expect_msg(
+        TIMEOUT,
+        HpvMsg::Disconnect {
+            peer: mock_self,
+            alive: false,
+        },
+    );
+}
+
+#[test]
+fn accept_handoff_into_free_passive_slots() {
+    let _ = System::new("test");
+    let (_, pasv_peer) = mock_hpv_peer();
+    let (_, handed_off1) = mock_hpv_peer();
+    let (_, handed_off2) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_passive_node(pasv_peer.clone())
+            .change_config(|c| c.max_passive_view_size = 2);
+    });
+
+    hpv.handle_handoff(hashset!{handed_off1.clone(), handed_off2.clone()});
+
+    assert_eq!(hpv.passive_view.len(), 2);
+    assert!(hpv.passive_view.contains(&pasv_peer));
+}
+
+#[test]
+fn stop_after_leave() {
+    let _ = System::new("test");
+    let (ap, actv_probe) = mock_hpv_peer();
+
+    let (_, self_peer, addr) = start_hyparview(|x| {
+        x.add_active_node(actv_probe.clone());
+    });
+
+    addr.do_send(Leave { handoff: false });
+    ap.expect_msg(
+        TIMEOUT,
+        HpvMsg::Disconnect {
+            peer: self_peer,
+            alive: false,
+        },
+    );
+
+    let (rx, view_recipient): (Receiver<Views>, Recipient<Views>) = mock_recipient();
+    addr.do_send(Inspect(view_recipient));
+    rx.expect_no_msg(TIMEOUT);
+}
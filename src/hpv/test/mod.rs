@@ -71,3 +71,27 @@ mod loquat;
 
 #[cfg(test)]
 mod discovery;
+
+#[cfg(test)]
+mod tcp;
+
+#[cfg(test)]
+mod failure;
+
+#[cfg(test)]
+mod seed;
+
+#[cfg(test)]
+mod events;
+
+#[cfg(test)]
+mod leave;
+
+#[cfg(test)]
+mod bootstrap;
+
+#[cfg(test)]
+mod aging;
+
+#[cfg(test)]
+mod restore;
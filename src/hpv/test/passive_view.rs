@@ -4,8 +4,9 @@ extern crate futures_channel;
 
 use self::actix::prelude::*;
 use super::*;
-use hpv::HpvMsg;
+use hpv::{Action, HpvMsg, Peer, Timer};
 use std::collections::HashSet;
+use std::net::SocketAddr;
 
 const SHUFFLE_INTERVAL: Duration = TIMEOUT;
 
@@ -14,7 +15,7 @@ fn periodically_initiate_shuffle() {
     let _ = System::new("test");
     let (ap, actv_probe) = mock_hpv_peer();
 
-    let (_, hpv) = start_hyparview(|x| {
+    let (_, self_peer, _) = start_hyparview(|x| {
         x.add_active_node(actv_probe.clone())
             .change_config(|c| c.shuffle_interval = SHUFFLE_INTERVAL);
     });
@@ -23,7 +24,7 @@ fn periodically_initiate_shuffle() {
         SHUFFLE_INTERVAL + TIMEOUT,
         HpvMsg::Shuffle {
             id: 0,
-            origin: hpv.clone().recipient().into(),
+            origin: self_peer.clone(),
             exchange: HashSet::new(),
             ttl: Config::default().shuffle_rwl,
         },
@@ -33,7 +34,7 @@ fn periodically_initiate_shuffle() {
         SHUFFLE_INTERVAL,
         HpvMsg::Shuffle {
             id: 1,
-            origin: hpv.clone().recipient().into(),
+            origin: self_peer.clone(),
             exchange: HashSet::new(),
             ttl: Config::default().shuffle_rwl,
         },
@@ -46,9 +47,8 @@ fn include_passive_and_active_peers_without_target_in_shuffle() {
 
     let (shuffle_receiver, active_probe) = mock_hpv_peer();
     let ((_, pp1), (_, pp2), (_, pp3)) = (mock_hpv_peer(), mock_hpv_peer(), mock_hpv_peer());
-    let (_, mock_self) = mock_hpv_peer();
 
-    let (_, mut hpv) = new_hyparview(|x| {
+    let mut hpv = new_hyparview(|x| {
         x.add_active_node(active_probe.clone())
             .add_passive_node(pp1.clone())
             .add_passive_node(pp2.clone())
@@ -59,8 +59,10 @@ fn include_passive_and_active_peers_without_target_in_shuffle() {
                 c.shuffle_passive = 2; // can choose a subset
             });
     });
+    let mock_self = hpv.self_peer().clone();
 
-    hpv.initiate_shuffle(mock_self.clone());
+    hpv.initiate_shuffle();
+    dispatch(&mut hpv);
     let shuffle = shuffle_receiver.recv_msg(TIMEOUT);
 
     match shuffle {
@@ -84,6 +86,32 @@ fn include_passive_and_active_peers_without_target_in_shuffle() {
     }
 }
 
+#[test]
+fn shuffle_with_passive_peer_when_isolated() {
+    let _ = System::new("test");
+    let ((_, pp1), (_, pp2)) = (mock_hpv_peer(), mock_hpv_peer());
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_passive_node(pp1.clone())
+            .add_passive_node(pp2.clone());
+    });
+
+    hpv.initiate_shuffle();
+    let shuffles: Vec<(SocketAddr, HashSet<Peer>)> = hpv.take_actions()
+        .into_iter()
+        .filter_map(|action| match action {
+            Action::Send(to, HpvMsg::Shuffle { exchange, .. }) => Some((to, exchange)),
+            _ => None,
+        })
+        .collect();
+
+    assert_eq!(shuffles.len(), 1);
+    let (ref target, ref exchange) = shuffles[0];
+    let other = if *target == pp1.addr { &pp2 } else { &pp1 };
+    assert!(*target == pp1.addr || *target == pp2.addr);
+    assert_eq!(*exchange, hashset!{other.clone()});
+}
+
 #[test]
 fn walk_random_if_ttl_positive_and_activeviewsize_gt1() {
     let _ = System::new("test");
@@ -91,7 +119,7 @@ fn walk_random_if_ttl_positive_and_activeviewsize_gt1() {
     let (shuffle_receiver, active_probe) = mock_hpv_peer();
     let (_, shuffle_initiator) = mock_hpv_peer();
 
-    let (_, mut hpv) = new_hyparview(|x| {
+    let mut hpv = new_hyparview(|x| {
         x.add_active_node(active_probe.clone())
             .add_active_node(shuffle_initiator.clone())
             .change_config(|c| {
@@ -100,11 +128,13 @@ fn walk_random_if_ttl_positive_and_activeviewsize_gt1() {
             });
     });
 
-    hpv.handle_shuffle(0, shuffle_initiator.clone(), HashSet::new(), 2);
+    hpv.handle_shuffle(42, shuffle_initiator.clone(), HashSet::new(), 2);
+    dispatch(&mut hpv);
+    // The forwarded request keeps the id of the initiator
     shuffle_receiver.expect_msg(
         TIMEOUT,
         HpvMsg::Shuffle {
-            id: 0,
+            id: 42,
             origin: shuffle_initiator,
             exchange: HashSet::new(),
             ttl: 1,
@@ -112,6 +142,29 @@ fn walk_random_if_ttl_positive_and_activeviewsize_gt1() {
     );
 }
 
+#[test]
+fn forwarding_shuffle_does_not_consume_own_ids() {
+    let _ = System::new("test");
+
+    let (_, active_probe) = mock_hpv_peer();
+    let (_, shuffle_initiator) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_active_node(active_probe.clone())
+            .add_active_node(shuffle_initiator.clone());
+    });
+
+    hpv.handle_shuffle(42, shuffle_initiator.clone(), HashSet::new(), 2);
+    hpv.take_actions();
+
+    hpv.initiate_shuffle();
+    let timeout = Config::default().shuffle_timeout;
+    assert!(
+        hpv.take_actions()
+            .contains(&Action::Schedule(Timer::ShuffleTimeout(0), timeout))
+    );
+}
+
 #[test]
 fn handle_shuffle_when_ttl0() {
     let _ = System::new("test");
@@ -121,7 +174,7 @@ fn handle_shuffle_when_ttl0() {
     let (_, pasv_peer) = mock_hpv_peer();
     let (_, shuffled_peer) = mock_hpv_peer();
 
-    let (_, mut hpv) = new_hyparview(|x| {
+    let mut hpv = new_hyparview(|x| {
         x.add_active_node(active_probe.clone())
             .add_active_node(shuffle_initiator.clone())
             .add_passive_node(pasv_peer.clone())
@@ -134,6 +187,7 @@ fn handle_shuffle_when_ttl0() {
         hashset!{shuffled_peer.clone()},
         1,
     );
+    dispatch(&mut hpv);
 
     reply_recv.expect_msg(
         TIMEOUT,
@@ -154,7 +208,7 @@ fn handle_shuffle_when_activeviewsize_0or1() {
     let (_, pasv_peer) = mock_hpv_peer();
     let (_, shuffled_peer) = mock_hpv_peer();
 
-    let (_, mut hpv) = new_hyparview(|x| {
+    let mut hpv = new_hyparview(|x| {
         x.add_active_node(active_probe.clone())
             .add_passive_node(pasv_peer.clone())
             .add_passive_node(shuffled_peer.clone());
@@ -166,6 +220,7 @@ fn handle_shuffle_when_activeviewsize_0or1() {
         hashset!{shuffled_peer.clone()},
         1,
     );
+    dispatch(&mut hpv);
 
     reply_recv.expect_msg(
         TIMEOUT,
@@ -186,9 +241,9 @@ fn merge_exchangeset_included_in_shuffle_reply() {
     let (_, actv_peer) = mock_hpv_peer();
     let (_, shuffled_peer) = mock_hpv_peer();
 
-    let (_, mut hpv) = new_hyparview(|x| {
+    let mut hpv = new_hyparview(|x| {
         x.add_active_node(actv_peer.clone())
-            .set_shuffling(1337, HashSet::default());
+            .add_outstanding_shuffle(1337, HashSet::default());
     });
 
     hpv.handle_shuffle_reply(1337, hashset!{shuffled_peer.clone()});
@@ -205,8 +260,8 @@ fn ignore_illegal_shuffle_replies() {
     let _ = System::new("test");
     let (_, shuffled_peer) = mock_hpv_peer();
 
-    let (_, mut hpv) = new_hyparview(|x| {
-        x.set_shuffling(1337, HashSet::default());
+    let mut hpv = new_hyparview(|x| {
+        x.add_outstanding_shuffle(1337, HashSet::default());
     });
 
     hpv.handle_shuffle_reply(1336, hashset!{shuffled_peer.clone()});
@@ -224,10 +279,10 @@ fn prioritize_removal_of_shuffled_peers() {
     let (_, shuffled_peer) = mock_hpv_peer();
     let (_, received_peer) = mock_hpv_peer();
 
-    let (_, mut hpv) = new_hyparview(|x| {
+    let mut hpv = new_hyparview(|x| {
         x.add_active_node(actv_peer.clone())
             .add_passive_node(shuffled_peer.clone())
-            .set_shuffling(1337, hashset!{shuffled_peer.clone()})
+            .add_outstanding_shuffle(1337, hashset!{shuffled_peer.clone()})
             .change_config(|c| {
                 c.max_passive_view_size = 1;
             });
@@ -241,3 +296,71 @@ fn prioritize_removal_of_shuffled_peers() {
     assert_eq!(hpv.passive_view.len(), 1);
     assert!(hpv.passive_view.contains(&received_peer));
 }
+
+#[test]
+fn reschedule_shuffle_timer() {
+    let _ = System::new("test");
+
+    let mut hpv = new_hyparview(|x| {
+        x.change_config(|c| c.shuffle_interval = SHUFFLE_INTERVAL);
+    });
+
+    assert!(
+        hpv.start()
+            .contains(&Action::Schedule(Timer::Shuffle, SHUFFLE_INTERVAL))
+    );
+    assert_eq!(
+        hpv.handle_timer(Timer::Shuffle),
+        vec![Action::Schedule(Timer::Shuffle, SHUFFLE_INTERVAL)]
+    );
+}
+
+#[test]
+fn merge_shuffle_reply_dropping_offered_peers_first() {
+    let _ = System::new("test");
+
+    let (_, actv_peer) = mock_hpv_peer();
+    let (_, offered_peer) = mock_hpv_peer();
+    let (_, received_peer) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_active_node(actv_peer.clone())
+            .add_passive_node(offered_peer.clone())
+            .change_config(|c| c.max_passive_view_size = 1);
+    });
+
+    hpv.initiate_shuffle();
+    let offer = hpv.take_actions()
+        .into_iter()
+        .filter_map(|action| match action {
+            Action::Send(_, HpvMsg::Shuffle { id, exchange, .. }) => Some((id, exchange)),
+            _ => None,
+        })
+        .next()
+        .expect("Did not send a shuffle request");
+    assert_eq!(offer, (0, hashset!{offered_peer.clone()}));
+
+    hpv.handle_shuffle_reply(0, hashset!{received_peer.clone()});
+
+    assert_eq!(hpv.passive_view.len(), 1);
+    assert!(hpv.passive_view.contains(&received_peer));
+
+    // A second reply to the same request is ignored
+    hpv.handle_shuffle_reply(0, hashset!{offered_peer.clone()});
+    assert!(hpv.passive_view.contains(&received_peer));
+}
+
+#[test]
+fn ignore_shuffle_reply_after_timeout() {
+    let _ = System::new("test");
+    let (_, shuffled_peer) = mock_hpv_peer();
+
+    let mut hpv = new_hyparview(|x| {
+        x.add_outstanding_shuffle(1337, HashSet::default());
+    });
+
+    hpv.handle_timer(Timer::ShuffleTimeout(1337));
+    hpv.handle_shuffle_reply(1337, hashset!{shuffled_peer.clone()});
+
+    assert_eq!(hpv.passive_view.len(), 0);
+}
@@ -0,0 +1,125 @@
+use hpv::{Action, Config, HpvMsg, HyParView, Peer, PeerId, Snapshot, SnapshotPeer, Timer};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+fn addr(port: u16) -> SocketAddr {
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port)
+}
+
+fn snapshot_peer(port: u16, active: bool, age: u64) -> SnapshotPeer {
+    SnapshotPeer {
+        id: PeerId::random(),
+        addr: addr(port),
+        active: active,
+        age: age,
+    }
+}
+
+fn restored_hyparview<F>(peers: Vec<SnapshotPeer>, setup: F) -> (HyParView, Vec<Action>)
+where
+    F: FnOnce(&mut Config) -> (),
+{
+    let mut config = Config::default();
+    config.seed_addrs = vec![addr(30999)];
+    setup(&mut config);
+    let self_peer = Peer::new(PeerId::random(), addr(30000));
+    let mut hpv = HyParView::new(self_peer, config).unwrap();
+    hpv.restore(Snapshot { peers: peers });
+    let actions = hpv.start();
+    (hpv, actions)
+}
+
+/// The addresses that the actions ask to become neighbours, with priority
+fn neighbour_requests(actions: &[Action]) -> Vec<SocketAddr> {
+    actions
+        .iter()
+        .filter_map(|action| match action {
+            Action::Send(to, HpvMsg::Neighbour { prio: true, .. }) => Some(*to),
+            _ => None,
+        })
+        .collect()
+}
+
+fn joins(actions: &[Action]) -> Vec<SocketAddr> {
+    actions
+        .iter()
+        .filter_map(|action| match action {
+            Action::Send(to, HpvMsg::Join(_)) => Some(*to),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn ask_formerly_active_peers_before_seeds() {
+    let peers = vec![
+        snapshot_peer(30001, false, 0),
+        snapshot_peer(30002, true, 0),
+        snapshot_peer(30003, false, 1),
+        snapshot_peer(30004, true, 1),
+    ];
+
+    let (hpv, actions) = restored_hyparview(peers, |c| c.max_active_view_size = 2);
+
+    assert_eq!(hpv.passive_view.len(), 4);
+    let mut requested = neighbour_requests(&actions);
+    requested.sort();
+    assert_eq!(requested, vec![addr(30002), addr(30004)]);
+    assert!(joins(&actions).is_empty());
+}
+
+#[test]
+fn fill_active_view_from_restored_passive_peers() {
+    let peers = vec![
+        snapshot_peer(30001, true, 0),
+        snapshot_peer(30002, false, 0),
+        snapshot_peer(30003, false, 0),
+    ];
+
+    let (_, actions) = restored_hyparview(peers, |c| c.max_active_view_size = 2);
+
+    let requested = neighbour_requests(&actions);
+    assert_eq!(requested.len(), 2);
+    assert!(requested.contains(&addr(30001)));
+}
+
+#[test]
+fn keep_freshest_peers_when_snapshot_overflows() {
+    let old = snapshot_peer(30001, false, 5);
+    let fresh = snapshot_peer(30002, false, 1);
+    let active = snapshot_peer(30003, true, 9);
+
+    let (hpv, _) = restored_hyparview(vec![old.clone(), fresh.clone(), active.clone()], |c| {
+        c.max_passive_view_size = 2;
+        c.shuffle_passive = 2;
+    });
+
+    assert!(!hpv.passive_view.contains(&old.peer()));
+    assert!(hpv.passive_view.contains(&fresh.peer()));
+    assert!(hpv.passive_view.contains(&active.peer()));
+}
+
+#[test]
+fn join_seeds_once_restored_peers_fail() {
+    let (mut hpv, actions) = restored_hyparview(vec![snapshot_peer(30001, true, 0)], |_| {});
+    let timeout = actions
+        .iter()
+        .filter_map(|action| match action {
+            Action::Schedule(Timer::NeighbourTimeout(id), _) => Some(*id),
+            _ => None,
+        })
+        .next()
+        .expect("Did not schedule a neighbour timeout");
+    assert!(joins(&actions).is_empty());
+
+    let actions = hpv.handle_timer(Timer::NeighbourTimeout(timeout));
+
+    assert_eq!(joins(&actions), vec![addr(30999)]);
+}
+
+#[test]
+fn join_seeds_without_snapshot() {
+    let (_, actions) = restored_hyparview(Vec::new(), |_| {});
+
+    assert!(neighbour_requests(&actions).is_empty());
+    assert_eq!(joins(&actions), vec![addr(30999)]);
+}
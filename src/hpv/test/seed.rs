@@ -0,0 +1,65 @@
+extern crate rand;
+
+use self::rand::rngs::SmallRng;
+use self::rand::SeedableRng;
+use hpv::{Action, Config, HyParView, Peer, PeerId, Timer};
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+const CLUSTER_SIZE: u16 = 8;
+
+/// Runs a small cluster of cores in lockstep, firing every timer after a round of deliveries, and
+/// records every action in the order it was produced
+fn run_cluster(seed: u64) -> Vec<(SocketAddr, Action)> {
+    let mut id_rng = SmallRng::from_seed([seed as u8; 16]);
+    let mut nodes: HashMap<SocketAddr, HyParView> = HashMap::new();
+    let mut order = Vec::new();
+    for i in 0..CLUSTER_SIZE {
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 20000 + i);
+        let mut config = Config::default();
+        config.seed = Some(seed + i as u64);
+        let peer = Peer::new(PeerId::random_from(&mut id_rng), addr);
+        nodes.insert(addr, HyParView::new(peer, config).unwrap());
+        order.push(addr);
+    }
+
+    let mut trace = Vec::new();
+    let mut pending: VecDeque<(SocketAddr, Action)> = VecDeque::new();
+    let contact = nodes[&order[0]].self_peer().clone();
+    for addr in &order[1..] {
+        let node = nodes.get_mut(addr).unwrap();
+        node.start();
+        node.handle_init_join(vec![contact.addr]);
+        for action in node.take_actions() {
+            pending.push_back((*addr, action));
+        }
+    }
+
+    for _round in 0..5 {
+        while let Some((from, action)) = pending.pop_front() {
+            trace.push((from, action.clone()));
+            if let Action::Send(to, msg) = action {
+                for next in nodes.get_mut(&to).unwrap().handle(msg) {
+                    pending.push_back((to, next));
+                }
+            }
+        }
+        for addr in &order {
+            let node = nodes.get_mut(addr).unwrap();
+            for timer in vec![Timer::Shuffle, Timer::Heartbeat] {
+                for next in node.handle_timer(timer) {
+                    pending.push_back((*addr, next));
+                }
+            }
+        }
+    }
+    trace
+}
+
+#[test]
+fn same_seed_replays_cluster_run() {
+    let first = run_cluster(42);
+    let second = run_cluster(42);
+    assert!(first.len() > CLUSTER_SIZE as usize);
+    assert_eq!(first, second);
+}
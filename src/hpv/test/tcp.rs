@@ -0,0 +1,135 @@
+extern crate actix;
+extern crate futures;
+extern crate futures_channel;
+
+use self::actix::prelude::*;
+use self::futures::Future;
+use super::*;
+use hpv::codec::read_frame;
+use hpv::{Dropped, HpvMsg, HyParViewActor, InitiateJoin, Inspect, JoinResult, Outbound, Peer,
+          PeerId, Register, TcpTransport, Views};
+use std::net::TcpListener;
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn start_tcp_node() -> (Peer, Addr<HyParViewActor>) {
+    let transport = TcpTransport::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let self_peer = Peer::new(PeerId::random(), transport.local_addr());
+    let transport = Arbiter::start(|_| transport);
+    let hpv = HyParViewActor::new(self_peer.clone(), transport.clone().recipient());
+    let hpv = Arbiter::start(|_| hpv);
+    transport.do_send(Register(self_peer.addr, hpv.clone().recipient()));
+    (self_peer, hpv)
+}
+
+#[test]
+fn join_over_tcp() {
+    let _ = System::new("test");
+    let (contact, contact_addr) = start_tcp_node();
+    let (joiner, joiner_addr) = start_tcp_node();
+
+    let joined = joiner_addr
+        .send(InitiateJoin(vec![contact.addr]))
+        .wait()
+        .unwrap()
+        .unwrap();
+    assert_eq!(joined, JoinResult::JoinCompleted(contact));
+
+    let (rx, view_recipient): (Receiver<Views>, Recipient<Views>) = mock_recipient();
+    contact_addr.do_send(Inspect(view_recipient));
+    let views = rx.recv_msg(TIMEOUT * 20);
+    assert!(views.active_view.contains(&joiner));
+}
+
+#[test]
+fn report_failed_delivery() {
+    let _ = System::new("test");
+    let closed = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let transport = TcpTransport::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let self_peer = Peer::new(PeerId::random(), transport.local_addr());
+    let transport = Arbiter::start(|_| transport);
+
+    let outcome = transport
+        .send(Outbound {
+            to: closed,
+            msg: HpvMsg::Heartbeat(self_peer),
+        })
+        .wait()
+        .unwrap();
+    assert!(!Dropped::is_dropped(&outcome.unwrap_err()));
+}
+
+#[test]
+fn drop_unencodable_message_but_keep_connection() {
+    let _ = System::new("test");
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let live = listener.local_addr().unwrap();
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        tx.send(read_frame(&mut stream).unwrap()).unwrap();
+    });
+    let transport = TcpTransport::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let self_peer = Peer::new(PeerId::random(), transport.local_addr());
+    let transport = Arbiter::start(|_| transport);
+
+    let outcome = transport
+        .send(Outbound {
+            to: live,
+            msg: HpvMsg::ForwardJoin {
+                joining: self_peer.clone(),
+                forwarder: self_peer.clone(),
+                ttl: u32::max_value() as usize + 1,
+            },
+        })
+        .wait()
+        .unwrap();
+    assert!(Dropped::is_dropped(&outcome.unwrap_err()));
+
+    transport
+        .send(Outbound {
+            to: live,
+            msg: HpvMsg::Heartbeat(self_peer.clone()),
+        })
+        .wait()
+        .unwrap()
+        .unwrap();
+    assert_eq!(rx.recv_msg(TIMEOUT * 20), HpvMsg::Heartbeat(self_peer));
+}
+
+#[test]
+fn deliver_while_another_peer_does_not_answer() {
+    let _ = System::new("test");
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let live = listener.local_addr().unwrap();
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        tx.send(read_frame(&mut stream).unwrap()).unwrap();
+    });
+    let transport = TcpTransport::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let self_peer = Peer::new(PeerId::random(), transport.local_addr());
+    let transport = Arbiter::start(|_| transport);
+
+    // Connecting to an unroutable address hangs until the connect timeout
+    transport.do_send(Outbound {
+        to: "10.255.255.1:9".parse().unwrap(),
+        msg: HpvMsg::Heartbeat(self_peer.clone()),
+    });
+    let start = Instant::now();
+    transport
+        .send(Outbound {
+            to: live,
+            msg: HpvMsg::Heartbeat(self_peer.clone()),
+        })
+        .wait()
+        .unwrap()
+        .unwrap();
+
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert_eq!(rx.recv_msg(TIMEOUT * 20), HpvMsg::Heartbeat(self_peer));
+}
@@ -4,34 +4,102 @@ extern crate futures_channel;
 
 use self::actix::dev::MessageResponse;
 use self::actix::prelude::*;
-use hpv::HpvMsg;
-use hpv::HyParViewActor;
-use hpv::Peer;
+use hpv::{Action, Config, Event, HpvMsg, HyParView, HyParViewActor, LocalNetwork, Outbound,
+          Peer, PeerId, Register, Timer};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::Receiver;
 use util::channelactor::ChannelActor;
 use util::channelactor::TrySendResult;
 
-/// Creates a default HyParViewActor that allows its configuration to be overridden
-pub fn new_hyparview<F>(setup: F) -> (Receiver<Peer>, HyParViewActor)
+static NEXT_PORT: AtomicUsize = AtomicUsize::new(10000);
+
+thread_local! {
+    /// Routes the messages of a single test, as each test runs on its own thread
+    static NETWORK: Addr<LocalNetwork> = Arbiter::start(|_| LocalNetwork::new());
+}
+
+fn network() -> Addr<LocalNetwork> {
+    NETWORK.with(|n| n.clone())
+}
+
+/// Creates a default HyParView (for a mocked self peer) that allows its configuration to be overridden
+pub fn new_hyparview<F>(setup: F) -> HyParView
 where
-    F: FnOnce(&mut HyParViewActor) -> (),
+    F: FnOnce(&mut HyParView) -> (),
 {
-    let (rx, mut hpv) = HyParViewActor::default();
+    let (_, self_peer) = mock_hpv_peer();
+    let mut hpv = HyParView::new(self_peer, Config::default()).unwrap();
     setup(&mut hpv);
-    (rx, hpv)
+    hpv
 }
 
-pub fn start_hyparview<F>(setup: F) -> (Receiver<Peer>, Addr<HyParViewActor>)
+/// Starts a `HyParViewActor` reachable through the test network, along with a receiver of its events
+pub fn start_hyparview<F>(setup: F) -> (Receiver<Event>, Peer, Addr<HyParViewActor>)
 where
-    F: FnOnce(&mut HyParViewActor) -> (),
+    F: FnOnce(&mut HyParView) -> (),
 {
-    let (rx, hpv) = new_hyparview(setup);
-    (rx, Arbiter::start(|_| hpv))
+    let self_peer = Peer::new(PeerId::random(), mock_addr());
+    let mut hpv = HyParViewActor::new(self_peer.clone(), network().recipient());
+    let (rx, subscriber) = mock_recipient();
+    hpv.subscribe(subscriber);
+    setup(hpv.protocol());
+    let addr = Arbiter::start(|_| hpv);
+    network().do_send(Register(self_peer.addr, addr.clone().recipient()));
+    (rx, self_peer, addr)
+}
+
+/// Delivers the pending messages of `hpv` to their (mocked) recipients, returning the discovered peers
+pub fn dispatch(hpv: &mut HyParView) -> Vec<Peer> {
+    dispatch_events(hpv)
+        .into_iter()
+        .filter_map(|event| match event {
+            Event::PeerDiscovered(peer) => Some(peer),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Delivers the pending messages of `hpv` to their (mocked) recipients, returning all events
+pub fn dispatch_events(hpv: &mut HyParView) -> Vec<Event> {
+    dispatch_actions(hpv.take_actions())
+}
+
+/// Delivers the messages among `actions` to their (mocked) recipients, returning all events
+pub fn dispatch_actions(actions: Vec<Action>) -> Vec<Event> {
+    let mut events = Vec::new();
+    for action in actions {
+        match action {
+            Action::Send(to, msg) => network().do_send(Outbound { to: to, msg: msg }),
+            Action::Emit(event) => events.push(event),
+            Action::Schedule(_, _) => {}
+        }
+    }
+    events
+}
+
+/// The number of the join attempt that the actions schedule a timeout for
+pub fn join_attempt(actions: &[Action]) -> u32 {
+    actions
+        .iter()
+        .filter_map(|action| match action {
+            Action::Schedule(Timer::JoinTimeout(attempt), _) => Some(*attempt),
+            _ => None,
+        })
+        .next()
+        .expect("Did not schedule a join timeout")
+}
+
+pub fn mock_addr() -> SocketAddr {
+    let port = NEXT_PORT.fetch_add(1, Ordering::SeqCst) as u16;
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port)
 }
 
 pub fn mock_hpv_peer() -> (Receiver<HpvMsg>, Peer) {
     let (recv, recp): (Receiver<HpvMsg>, Recipient<HpvMsg>) = mock_recipient();
-    (recv, recp.into())
+    let peer = Peer::new(PeerId::random(), mock_addr());
+    network().do_send(Register(peer.addr, recp));
+    (recv, peer)
 }
 
 pub fn mock_recipient<T>() -> (Receiver<T>, Recipient<T>)
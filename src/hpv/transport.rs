@@ -0,0 +1,129 @@
+use super::actix::prelude::*;
+use super::actix::Recipient;
+use super::codec::EncodeError;
+use super::HpvMsg;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::io::ErrorKind::NotFound;
+use std::net::SocketAddr;
+
+/// A protocol message to be delivered at the given address. Transports carry `HpvMsg`s unless
+/// told otherwise, but any protocol layered on top of `HyParView` can use them for its own messages.
+/// A failed delivery means that the peer could not be reached, unless the error wraps `Dropped`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Outbound<M = HpvMsg> {
+    pub to: SocketAddr,
+    pub msg: M,
+}
+
+impl<M: 'static> Message for Outbound<M> {
+    type Result = Result<(), io::Error>;
+}
+
+/// Why a transport gave up on a message without trying to deliver it, which says nothing about the
+/// peer it was meant for
+#[derive(Debug)]
+pub enum Dropped {
+    /// Too many messages already wait to be written to the peer
+    QueueFull(SocketAddr),
+    /// The message cannot be written in the wire format
+    Unencodable(EncodeError),
+}
+
+impl Dropped {
+    /// Whether the failed delivery of a message reports a `Dropped` message rather than a peer that
+    /// could not be reached
+    pub fn is_dropped(error: &io::Error) -> bool {
+        error.get_ref().map_or(false, |e| e.is::<Dropped>())
+    }
+}
+
+impl fmt::Display for Dropped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Dropped::QueueFull(to) => write!(f, "Send queue to {} is full", to),
+            Dropped::Unencodable(e) => write!(f, "Cannot encode message: {}", e),
+        }
+    }
+}
+
+impl Error for Dropped {}
+
+/// Reports that a message could not be delivered at the given address
+pub struct Unreachable(pub SocketAddr);
+
+impl Message for Unreachable {
+    type Result = Result<(), io::Error>;
+}
+
+/// Makes a local actor reachable at the given address
+pub struct Register<M = HpvMsg>(pub SocketAddr, pub Recipient<M>)
+where
+    M: Message<Result = Result<(), io::Error>> + Send;
+
+impl<M> Message for Register<M>
+where
+    M: Message<Result = Result<(), io::Error>> + Send + 'static,
+{
+    type Result = Result<(), io::Error>;
+}
+
+/// Routes `Outbound` messages between actors living in the same actix `System`. Actors are
+/// registered at the address of the peer they act for.
+pub struct LocalNetwork<M = HpvMsg>
+where
+    M: Message<Result = Result<(), io::Error>> + Send,
+{
+    routes: HashMap<SocketAddr, Recipient<M>>,
+}
+
+impl<M> LocalNetwork<M>
+where
+    M: Message<Result = Result<(), io::Error>> + Send,
+{
+    pub fn new() -> LocalNetwork<M> {
+        LocalNetwork {
+            routes: HashMap::new(),
+        }
+    }
+}
+
+impl<M> Actor for LocalNetwork<M>
+where
+    M: Message<Result = Result<(), io::Error>> + Send + 'static,
+{
+    type Context = Context<Self>;
+}
+
+impl<M> Handler<Register<M>> for LocalNetwork<M>
+where
+    M: Message<Result = Result<(), io::Error>> + Send + 'static,
+{
+    type Result = Result<(), io::Error>;
+
+    fn handle(&mut self, msg: Register<M>, _ctx: &mut Context<Self>) -> Self::Result {
+        self.routes.insert(msg.0, msg.1);
+        Ok(())
+    }
+}
+
+impl<M> Handler<Outbound<M>> for LocalNetwork<M>
+where
+    M: Message<Result = Result<(), io::Error>> + Send + 'static,
+{
+    type Result = Result<(), io::Error>;
+
+    fn handle(&mut self, msg: Outbound<M>, _ctx: &mut Context<Self>) -> Self::Result {
+        match self.routes.get(&msg.to) {
+            Some(recipient) => recipient
+                .do_send(msg.msg)
+                .map_err(|_| io::Error::new(NotFound, "Recipient no longer available")),
+            None => Err(io::Error::new(
+                NotFound,
+                format!("No peer registered at {}", msg.to),
+            )),
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use super::actix::Message;
-use super::{HyParViewActor, Peer};
+use super::{HyParView, Peer};
 use bounded_set::BoundedSet;
 use std::io;
 
@@ -14,10 +14,10 @@ impl Message for Views {
 }
 
 impl Views {
-    pub fn from_hyparview(actor: &HyParViewActor) -> Views {
+    pub fn from_hyparview(hpv: &HyParView) -> Views {
         Views {
-            active_view: actor.active_view.clone(),
-            passive_view: actor.passive_view.clone(),
+            active_view: hpv.active_view.clone(),
+            passive_view: hpv.passive_view.clone(),
         }
     }
 }
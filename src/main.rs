@@ -1,10 +1,18 @@
 #[macro_use]
 extern crate maplit;
+#[macro_use]
+extern crate serde_derive;
+#[cfg(test)]
+#[macro_use]
+extern crate quickcheck;
 
 fn main() {
     println!("Hello, world!");
 }
 
+pub mod analysis;
 pub mod bounded_set;
 pub mod hpv;
+pub mod plumtree;
+pub mod sim;
 pub mod util;
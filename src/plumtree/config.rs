@@ -0,0 +1,25 @@
+use std::time::Duration;
+
+#[derive(Clone)]
+pub struct Config {
+    /// How long to wait for an announced message to arrive before grafting its announcer
+    pub missing_timeout: Duration,
+    /// How long to wait for a grafted message before grafting the next announcer
+    pub graft_timeout: Duration,
+    /// How many of the most recent payloads to keep for answering grafts
+    pub retained_payloads: usize,
+    /// How many of the most recent broadcast ids to keep for discarding duplicates. Duplicates
+    /// keep arriving for a while after grafts stop, so this should exceed `retained_payloads`.
+    pub retained_ids: usize,
+}
+
+impl Config {
+    pub fn default() -> Config {
+        Config {
+            missing_timeout: Duration::from_millis(500),
+            graft_timeout: Duration::from_millis(250),
+            retained_payloads: 1000,
+            retained_ids: 10000,
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use super::actix::Message;
+use super::futures::sync::mpsc::UnboundedReceiver;
+use hpv::{Peer, PeerId};
+use std::fmt;
+use std::io;
+
+/// Identifies a broadcast by the peer that originated it and its sequence number there
+#[derive(Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, Debug)]
+pub struct MessageId {
+    pub origin: PeerId,
+    pub seq: u64,
+}
+
+impl fmt::Display for MessageId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}#{}", self.origin, self.seq)
+    }
+}
+
+/// The messages exchanged between `Plumtree` instances. Each carries its sender, so that the
+/// receiver can maintain its push sets.
+#[derive(Eq, PartialEq, Clone)]
+pub enum PlumtreeMsg {
+    /// The payload of a broadcast, pushed eagerly along the tree
+    Gossip {
+        id: MessageId,
+        round: u32,
+        payload: Vec<u8>,
+        sender: Peer,
+    },
+    /// Announces a broadcast to a lazy push peer
+    IHave {
+        id: MessageId,
+        round: u32,
+        sender: Peer,
+    },
+    /// Requests a missing broadcast, and makes the link part of the tree
+    Graft {
+        id: MessageId,
+        round: u32,
+        sender: Peer,
+    },
+    /// Removes a redundant link from the tree
+    Prune(Peer),
+}
+
+impl fmt::Debug for PlumtreeMsg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlumtreeMsg::Gossip { id, sender, .. } => write!(f, "Gossip({}, {})", id, sender),
+            PlumtreeMsg::IHave { id, sender, .. } => write!(f, "IHave({}, {})", id, sender),
+            PlumtreeMsg::Graft { id, sender, .. } => write!(f, "Graft({}, {})", id, sender),
+            PlumtreeMsg::Prune(p) => write!(f, "Prune({})", p),
+        }
+    }
+}
+
+impl Message for PlumtreeMsg {
+    type Result = Result<(), io::Error>;
+}
+
+/// Requests the payload to be broadcast to all peers, replying with its id
+pub struct Broadcast(pub Vec<u8>);
+
+impl Message for Broadcast {
+    type Result = MessageId;
+}
+
+/// Requests a stream of all subsequently delivered broadcasts
+pub struct SubscribeDeliveries;
+
+impl Message for SubscribeDeliveries {
+    type Result = UnboundedReceiver<Delivery>;
+}
+
+/// A broadcast that reached this peer
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Delivery {
+    pub id: MessageId,
+    pub payload: Vec<u8>,
+}
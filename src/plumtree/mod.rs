@@ -0,0 +1,129 @@
+extern crate actix;
+extern crate futures;
+
+use self::actix::prelude::*;
+use self::actix::Recipient;
+use self::futures::sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use self::futures::Future;
+use hpv::{Event, Outbound, Peer};
+use std::io;
+
+mod config;
+pub use self::config::*;
+
+mod message;
+pub use self::message::*;
+
+mod protocol;
+pub use self::protocol::*;
+
+type TransportRecipient = Recipient<Outbound<PlumtreeMsg>>;
+
+/// Hosts a `Plumtree` state machine inside an actix `System`. Its neighbours follow the active view
+/// of a `HyParViewActor`, by subscribing this actor to its `Event`s.
+pub struct PlumtreeActor {
+    plumtree: Plumtree,
+    transport: TransportRecipient,
+    deliveries: Vec<UnboundedSender<Delivery>>,
+}
+
+impl PlumtreeActor {
+    /// Creates an actor for `self_peer` that dispatches its messages through `transport`, such as a
+    /// `hpv::LocalNetwork<PlumtreeMsg>`
+    pub fn new(self_peer: Peer, transport: TransportRecipient) -> PlumtreeActor {
+        PlumtreeActor {
+            plumtree: Plumtree::new(self_peer, Config::default()),
+            transport: transport,
+            deliveries: Vec::new(),
+        }
+    }
+
+    pub fn protocol(&mut self) -> &mut Plumtree {
+        &mut self.plumtree
+    }
+
+    /// A stream of all subsequently delivered broadcasts, including our own
+    pub fn deliveries(&mut self) -> UnboundedReceiver<Delivery> {
+        let (tx, rx) = unbounded();
+        self.deliveries.push(tx);
+        rx
+    }
+
+    fn dispatch(&mut self, actions: Vec<Action>, ctx: &mut Context<Self>) {
+        for action in actions {
+            match action {
+                Action::Send(addr, msg) => {
+                    // Lost messages are recovered through announcements, and failed peers are
+                    // removed by the peer sampling service
+                    let delivery = self.transport
+                        .send(Outbound { to: addr, msg: msg })
+                        .then(move |result| {
+                            match result {
+                                Ok(Ok(())) => {}
+                                _ => println!("[WARN] Failed to send broadcast message to {}", addr),
+                            };
+                            Ok(())
+                        });
+                    Arbiter::spawn(delivery);
+                }
+                Action::Deliver(delivery) => self.deliveries
+                    .retain(|d| d.unbounded_send(delivery.clone()).is_ok()),
+                Action::Schedule(timer, delay) => {
+                    ctx.run_later(delay, move |actor: &mut PlumtreeActor, ctx| {
+                        let actions = actor.plumtree.handle_timer(timer);
+                        actor.dispatch(actions, ctx);
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl Actor for PlumtreeActor {
+    type Context = Context<Self>;
+}
+
+impl Handler<PlumtreeMsg> for PlumtreeActor {
+    type Result = Result<(), io::Error>;
+
+    fn handle(&mut self, msg: PlumtreeMsg, ctx: &mut Context<Self>) -> Self::Result {
+        let actions = self.plumtree.handle(msg);
+        self.dispatch(actions, ctx);
+        Ok(())
+    }
+}
+
+impl Handler<Event> for PlumtreeActor {
+    type Result = Result<(), io::Error>;
+
+    fn handle(&mut self, event: Event, ctx: &mut Context<Self>) -> Self::Result {
+        let actions = match event {
+            Event::ActivePeerAdded(peer) => self.plumtree.neighbour_up(peer),
+            Event::ActivePeerRemoved(peer) => self.plumtree.neighbour_down(&peer),
+            _ => Vec::new(),
+        };
+        self.dispatch(actions, ctx);
+        Ok(())
+    }
+}
+
+impl Handler<Broadcast> for PlumtreeActor {
+    type Result = MessageResult<Broadcast>;
+
+    fn handle(&mut self, msg: Broadcast, ctx: &mut Context<Self>) -> Self::Result {
+        let (id, actions) = self.plumtree.broadcast(msg.0);
+        self.dispatch(actions, ctx);
+        MessageResult(id)
+    }
+}
+
+impl Handler<SubscribeDeliveries> for PlumtreeActor {
+    type Result = MessageResult<SubscribeDeliveries>;
+
+    fn handle(&mut self, _msg: SubscribeDeliveries, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.deliveries())
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,283 @@
+use super::{Config, Delivery, MessageId, PlumtreeMsg};
+use hpv::Peer;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::mem;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Timers that `Plumtree` asks its environment to schedule
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Debug)]
+pub enum Timer {
+    /// Checks whether an announced broadcast has arrived in the meantime
+    Missing(MessageId),
+}
+
+/// The effects of handling an input, to be carried out by the environment hosting `Plumtree`
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum Action {
+    /// Dispatch the message to the peer reachable at the address
+    Send(SocketAddr, PlumtreeMsg),
+    /// Hand a broadcast to the application
+    Deliver(Delivery),
+    /// Feed the timer back through `Plumtree::handle_timer` once the duration has elapsed
+    Schedule(Timer, Duration),
+}
+
+/// Epidemic broadcast trees (Plumtree) as a synchronous state machine. Broadcasts are pushed
+/// eagerly along a spanning tree and announced lazily to the remaining neighbours, which repair the
+/// tree when an announced message fails to arrive. Neighbours are supplied by a peer sampling
+/// service, normally the active view of `HyParView`.
+pub struct Plumtree {
+    self_peer: Peer,
+    config: Config,
+    pub(super) eager_push_peers: BTreeSet<Peer>,
+    pub(super) lazy_push_peers: BTreeSet<Peer>,
+    next_seq: u64,
+    // the most recently delivered broadcasts, to discard duplicates, oldest first
+    received: HashSet<MessageId>,
+    received_order: VecDeque<MessageId>,
+    // the payloads of the most recently delivered broadcasts, to answer grafts, oldest first
+    payloads: HashMap<MessageId, Vec<u8>>,
+    payload_order: VecDeque<MessageId>,
+    // announcers of broadcasts that were not received yet, in order of announcement
+    missing: HashMap<MessageId, Vec<(Peer, u32)>>,
+    actions: Vec<Action>,
+}
+
+impl Plumtree {
+    pub fn new(self_peer: Peer, config: Config) -> Plumtree {
+        Plumtree {
+            self_peer: self_peer,
+            config: config,
+            eager_push_peers: BTreeSet::new(),
+            lazy_push_peers: BTreeSet::new(),
+            next_seq: 0,
+            received: HashSet::new(),
+            received_order: VecDeque::new(),
+            payloads: HashMap::new(),
+            payload_order: VecDeque::new(),
+            missing: HashMap::new(),
+            actions: Vec::new(),
+        }
+    }
+
+    pub fn self_peer(&self) -> &Peer {
+        &self.self_peer
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    /// Drains the actions accumulated since the last call
+    pub fn take_actions(&mut self) -> Vec<Action> {
+        mem::replace(&mut self.actions, Vec::new())
+    }
+
+    fn send(&mut self, to: &Peer, msg: PlumtreeMsg) {
+        self.actions.push(Action::Send(to.addr, msg));
+    }
+
+    fn is_neighbour(&self, peer: &Peer) -> bool {
+        self.eager_push_peers.contains(peer) || self.lazy_push_peers.contains(peer)
+    }
+
+    /// Makes the link to a neighbour part of the tree. Peers that the peer sampling service did not
+    /// report as neighbours, or already reported down, are left out.
+    fn graft_neighbour(&mut self, peer: &Peer) {
+        if self.lazy_push_peers.remove(peer) {
+            self.eager_push_peers.insert(peer.clone());
+        }
+    }
+}
+
+impl Plumtree {
+    /// A new neighbour starts out as part of the tree
+    pub fn neighbour_up(&mut self, peer: Peer) -> Vec<Action> {
+        if peer != self.self_peer {
+            self.lazy_push_peers.remove(&peer);
+            self.eager_push_peers.insert(peer);
+        }
+        self.take_actions()
+    }
+
+    pub fn neighbour_down(&mut self, peer: &Peer) -> Vec<Action> {
+        self.eager_push_peers.remove(peer);
+        self.lazy_push_peers.remove(peer);
+        for announcers in self.missing.values_mut() {
+            announcers.retain(|&(ref p, _)| p != peer);
+        }
+        self.take_actions()
+    }
+
+    /// Delivers the payload locally and disseminates it to all peers
+    pub fn broadcast(&mut self, payload: Vec<u8>) -> (MessageId, Vec<Action>) {
+        let id = MessageId {
+            origin: self.self_peer.id,
+            seq: self.next_seq,
+        };
+        self.next_seq += 1;
+
+        self.deliver(id, payload.clone());
+        let sender = self.self_peer.clone();
+        self.push(id, 0, &payload, &sender);
+        (id, self.take_actions())
+    }
+
+    pub fn handle(&mut self, msg: PlumtreeMsg) -> Vec<Action> {
+        match msg {
+            PlumtreeMsg::Gossip {
+                id,
+                round,
+                payload,
+                sender,
+            } => self.handle_gossip(id, round, payload, sender),
+            PlumtreeMsg::IHave { id, round, sender } => self.handle_ihave(id, round, sender),
+            PlumtreeMsg::Graft { id, round, sender } => self.handle_graft(id, round, sender),
+            PlumtreeMsg::Prune(sender) => self.handle_prune(sender),
+        };
+        self.take_actions()
+    }
+
+    pub fn handle_timer(&mut self, timer: Timer) -> Vec<Action> {
+        match timer {
+            Timer::Missing(id) => self.handle_missing(id),
+        };
+        self.take_actions()
+    }
+
+    pub fn handle_gossip(&mut self, id: MessageId, round: u32, payload: Vec<u8>, sender: Peer) {
+        if self.received.contains(&id) {
+            // A redundant path, keep the link for announcements only
+            self.handle_prune(sender.clone());
+            let prune = PlumtreeMsg::Prune(self.self_peer.clone());
+            self.send(&sender, prune);
+        } else {
+            self.missing.remove(&id);
+            self.deliver(id, payload.clone());
+            self.push(id, round.saturating_add(1), &payload, &sender);
+            self.graft_neighbour(&sender);
+        }
+    }
+
+    pub fn handle_ihave(&mut self, id: MessageId, round: u32, sender: Peer) {
+        if self.received.contains(&id) || !self.is_neighbour(&sender) {
+            return;
+        }
+        let missing_timeout = self.config.missing_timeout;
+        let announcers = self.missing.entry(id).or_insert_with(Vec::new);
+        if announcers.is_empty() {
+            self.actions
+                .push(Action::Schedule(Timer::Missing(id), missing_timeout));
+        }
+        announcers.push((sender, round));
+    }
+
+    pub fn handle_graft(&mut self, id: MessageId, round: u32, sender: Peer) {
+        // Only neighbours were sent announcements to graft on
+        if !self.is_neighbour(&sender) {
+            return;
+        }
+        self.graft_neighbour(&sender);
+        let gossip = self.payloads.get(&id).map(|payload| PlumtreeMsg::Gossip {
+            id: id,
+            round: round,
+            payload: payload.clone(),
+            sender: self.self_peer.clone(),
+        });
+        match gossip {
+            Some(gossip) => self.send(&sender, gossip),
+            None => println!("[WARN] Received graft for unknown broadcast {}", id),
+        }
+    }
+
+    pub fn handle_prune(&mut self, sender: Peer) {
+        if self.eager_push_peers.remove(&sender) {
+            self.lazy_push_peers.insert(sender);
+        }
+    }
+
+    /// The announced broadcast did not arrive in time, so graft the first announcer we have not
+    /// tried yet into the tree
+    fn handle_missing(&mut self, id: MessageId) {
+        if self.received.contains(&id) {
+            return;
+        }
+        let next = match self.missing.get_mut(&id) {
+            Some(ref mut announcers) if !announcers.is_empty() => announcers.remove(0),
+            _ => {
+                self.missing.remove(&id);
+                return;
+            }
+        };
+
+        let (announcer, round) = next;
+        self.actions
+            .push(Action::Schedule(Timer::Missing(id), self.config.graft_timeout));
+        self.graft_neighbour(&announcer);
+        let graft = PlumtreeMsg::Graft {
+            id: id,
+            round: round,
+            sender: self.self_peer.clone(),
+        };
+        self.send(&announcer, graft);
+    }
+
+    fn deliver(&mut self, id: MessageId, payload: Vec<u8>) {
+        self.received.insert(id);
+        self.received_order.push_back(id);
+        while self.received_order.len() > self.config.retained_ids {
+            if let Some(old) = self.received_order.pop_front() {
+                self.received.remove(&old);
+            }
+        }
+        self.payloads.insert(id, payload.clone());
+        self.payload_order.push_back(id);
+        while self.payload_order.len() > self.config.retained_payloads {
+            if let Some(old) = self.payload_order.pop_front() {
+                self.payloads.remove(&old);
+            }
+        }
+        self.actions.push(Action::Deliver(Delivery {
+            id: id,
+            payload: payload,
+        }));
+    }
+
+    /// Sends the payload to the eager push peers and announces it to the lazy push peers, except
+    /// for the peer we received it from
+    fn push(&mut self, id: MessageId, round: u32, payload: &[u8], from: &Peer) {
+        let gossip = PlumtreeMsg::Gossip {
+            id: id,
+            round: round,
+            payload: payload.to_vec(),
+            sender: self.self_peer.clone(),
+        };
+        let ihave = PlumtreeMsg::IHave {
+            id: id,
+            round: round,
+            sender: self.self_peer.clone(),
+        };
+
+        let eager: Vec<Peer> = self.eager_push_peers
+            .iter()
+            .filter(|p| *p != from)
+            .cloned()
+            .collect();
+        let lazy: Vec<Peer> = self.lazy_push_peers
+            .iter()
+            .filter(|p| *p != from)
+            .cloned()
+            .collect();
+        for p in eager {
+            self.send(&p, gossip.clone());
+        }
+        for p in lazy {
+            self.send(&p, ihave.clone());
+        }
+    }
+}
@@ -0,0 +1,48 @@
+extern crate actix;
+extern crate futures;
+
+use self::actix::prelude::*;
+use self::futures::{Future, Stream};
+use super::*;
+use hpv::{Event, LocalNetwork, Register};
+use plumtree::{Broadcast, Delivery, PlumtreeActor, PlumtreeMsg, SubscribeDeliveries};
+
+fn start_plumtree(network: &Addr<LocalNetwork<PlumtreeMsg>>) -> (Peer, Addr<PlumtreeActor>) {
+    let self_peer = mock_peer();
+    let actor = PlumtreeActor::new(self_peer.clone(), network.clone().recipient());
+    let addr = Arbiter::start(|_| actor);
+    network.do_send(Register(self_peer.addr, addr.clone().recipient()));
+    (self_peer, addr)
+}
+
+fn connect(p1: (&Peer, &Addr<PlumtreeActor>), p2: (&Peer, &Addr<PlumtreeActor>)) {
+    p1.1.do_send(Event::ActivePeerAdded(p2.0.clone()));
+    p2.1.do_send(Event::ActivePeerAdded(p1.0.clone()));
+}
+
+#[test]
+fn broadcast_along_active_views() {
+    let _ = System::new("test");
+    let network = Arbiter::start(|_| LocalNetwork::new());
+    let (a, a_addr) = start_plumtree(&network);
+    let (b, b_addr) = start_plumtree(&network);
+    let (c, c_addr) = start_plumtree(&network);
+    connect((&a, &a_addr), (&b, &b_addr));
+    connect((&b, &b_addr), (&c, &c_addr));
+
+    let deliveries = c_addr.send(SubscribeDeliveries).wait().unwrap();
+    let id = a_addr.send(Broadcast(vec![4, 2])).wait().unwrap();
+
+    let (delivery, _) = deliveries
+        .into_future()
+        .wait()
+        .map_err(|_| ())
+        .expect("Broadcast was not delivered");
+    assert_eq!(
+        delivery,
+        Some(Delivery {
+            id: id,
+            payload: vec![4, 2],
+        })
+    );
+}
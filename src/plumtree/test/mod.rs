@@ -0,0 +1,40 @@
+use hpv::{Peer, PeerId};
+use plumtree::{Action, Config, Plumtree, PlumtreeMsg};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_PORT: AtomicUsize = AtomicUsize::new(30000);
+
+pub fn mock_peer() -> Peer {
+    let port = NEXT_PORT.fetch_add(1, Ordering::SeqCst) as u16;
+    Peer::new(
+        PeerId::random(),
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port),
+    )
+}
+
+/// Creates a `Plumtree` for a mocked self peer with the given eager push peers
+pub fn new_plumtree(neighbours: &[Peer]) -> Plumtree {
+    let mut plumtree = Plumtree::new(mock_peer(), Config::default());
+    for n in neighbours {
+        plumtree.neighbour_up(n.clone());
+    }
+    plumtree
+}
+
+/// The messages among the actions, along with their destination
+pub fn sent(actions: &[Action]) -> Vec<(SocketAddr, PlumtreeMsg)> {
+    actions
+        .iter()
+        .filter_map(|action| match action {
+            Action::Send(to, msg) => Some((*to, msg.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tree;
+
+#[cfg(test)]
+mod broadcast;
@@ -0,0 +1,334 @@
+use super::*;
+use plumtree::{Action, Delivery, PlumtreeMsg, Timer};
+
+#[test]
+fn broadcast_delivers_locally_and_pushes_eagerly() {
+    let (n1, n2) = (mock_peer(), mock_peer());
+    let mut plumtree = new_plumtree(&[n1.clone(), n2.clone()]);
+    let self_peer = plumtree.self_peer().clone();
+
+    let (id, actions) = plumtree.broadcast(vec![1, 2, 3]);
+
+    assert_eq!(id.origin, self_peer.id);
+    assert!(actions.contains(&Action::Deliver(Delivery {
+        id: id,
+        payload: vec![1, 2, 3],
+    })));
+    let gossip = PlumtreeMsg::Gossip {
+        id: id,
+        round: 0,
+        payload: vec![1, 2, 3],
+        sender: self_peer.clone(),
+    };
+    let sent = sent(&actions);
+    assert_eq!(sent.len(), 2);
+    assert!(sent.contains(&(n1.addr, gossip.clone())));
+    assert!(sent.contains(&(n2.addr, gossip.clone())));
+}
+
+#[test]
+fn forward_gossip_except_to_sender() {
+    let (origin, n1, n2) = (mock_peer(), mock_peer(), mock_peer());
+    let mut plumtree = new_plumtree(&[origin.clone(), n1.clone()]);
+    plumtree.handle_prune(n1.clone());
+    plumtree.neighbour_up(n2.clone());
+    let self_peer = plumtree.self_peer().clone();
+    let mut remote = new_plumtree(&[]);
+    let (id, _) = remote.broadcast(vec![7]);
+
+    let actions = plumtree.handle(PlumtreeMsg::Gossip {
+        id: id,
+        round: 0,
+        payload: vec![7],
+        sender: origin.clone(),
+    });
+
+    assert_eq!(
+        sent(&actions),
+        vec![
+            (
+                n2.addr,
+                PlumtreeMsg::Gossip {
+                    id: id,
+                    round: 1,
+                    payload: vec![7],
+                    sender: self_peer.clone(),
+                },
+            ),
+            (
+                n1.addr,
+                PlumtreeMsg::IHave {
+                    id: id,
+                    round: 1,
+                    sender: self_peer.clone(),
+                },
+            ),
+        ]
+    );
+}
+
+#[test]
+fn prune_redundant_sender() {
+    let (n1, n2) = (mock_peer(), mock_peer());
+    let mut plumtree = new_plumtree(&[n1.clone(), n2.clone()]);
+    let self_peer = plumtree.self_peer().clone();
+    let (id, _) = new_plumtree(&[]).broadcast(vec![7]);
+    let gossip = |sender: &Peer| PlumtreeMsg::Gossip {
+        id: id,
+        round: 0,
+        payload: vec![7],
+        sender: sender.clone(),
+    };
+
+    plumtree.handle(gossip(&n1));
+    let actions = plumtree.handle(gossip(&n2));
+
+    assert_eq!(sent(&actions), vec![(n2.addr, PlumtreeMsg::Prune(self_peer))]);
+    assert!(plumtree.eager_push_peers.contains(&n1));
+    assert!(plumtree.lazy_push_peers.contains(&n2));
+    assert!(!actions.iter().any(|a| match a {
+        Action::Deliver(_) => true,
+        _ => false,
+    }));
+}
+
+#[test]
+fn move_pruning_peer_to_lazy_push_peers() {
+    let n1 = mock_peer();
+    let mut plumtree = new_plumtree(&[n1.clone()]);
+
+    plumtree.handle(PlumtreeMsg::Prune(n1.clone()));
+
+    assert!(plumtree.eager_push_peers.is_empty());
+    assert!(plumtree.lazy_push_peers.contains(&n1));
+}
+
+#[test]
+fn graft_announcer_of_missing_broadcast() {
+    let (n1, n2) = (mock_peer(), mock_peer());
+    let mut plumtree = new_plumtree(&[n1.clone(), n2.clone()]);
+    plumtree.handle_prune(n1.clone());
+    plumtree.handle_prune(n2.clone());
+    let self_peer = plumtree.self_peer().clone();
+    let (id, _) = new_plumtree(&[]).broadcast(vec![7]);
+
+    let actions = plumtree.handle(PlumtreeMsg::IHave {
+        id: id,
+        round: 2,
+        sender: n1.clone(),
+    });
+    assert_eq!(
+        actions,
+        vec![Action::Schedule(Timer::Missing(id), Config::default().missing_timeout)]
+    );
+    // Only the first announcement starts a timer
+    let actions = plumtree.handle(PlumtreeMsg::IHave {
+        id: id,
+        round: 3,
+        sender: n2.clone(),
+    });
+    assert!(actions.is_empty());
+
+    let actions = plumtree.handle_timer(Timer::Missing(id));
+    assert_eq!(
+        sent(&actions),
+        vec![(
+            n1.addr,
+            PlumtreeMsg::Graft {
+                id: id,
+                round: 2,
+                sender: self_peer.clone(),
+            },
+        )]
+    );
+    assert!(actions.contains(&Action::Schedule(
+        Timer::Missing(id),
+        Config::default().graft_timeout
+    )));
+    assert!(plumtree.eager_push_peers.contains(&n1));
+
+    // The grafted peer did not deliver either, so try the next announcer
+    let actions = plumtree.handle_timer(Timer::Missing(id));
+    assert_eq!(
+        sent(&actions),
+        vec![(
+            n2.addr,
+            PlumtreeMsg::Graft {
+                id: id,
+                round: 3,
+                sender: self_peer.clone(),
+            },
+        )]
+    );
+}
+
+#[test]
+fn ignore_missing_timer_after_delivery() {
+    let n1 = mock_peer();
+    let mut plumtree = new_plumtree(&[n1.clone()]);
+    let (id, _) = new_plumtree(&[]).broadcast(vec![7]);
+
+    plumtree.handle(PlumtreeMsg::IHave {
+        id: id,
+        round: 0,
+        sender: n1.clone(),
+    });
+    plumtree.handle(PlumtreeMsg::Gossip {
+        id: id,
+        round: 0,
+        payload: vec![7],
+        sender: n1.clone(),
+    });
+
+    assert!(plumtree.handle_timer(Timer::Missing(id)).is_empty());
+}
+
+#[test]
+fn answer_graft_with_gossip() {
+    let n1 = mock_peer();
+    let mut plumtree = new_plumtree(&[]);
+    plumtree.neighbour_up(n1.clone());
+    plumtree.handle_prune(n1.clone());
+    let self_peer = plumtree.self_peer().clone();
+    let (id, _) = plumtree.broadcast(vec![7]);
+
+    let actions = plumtree.handle(PlumtreeMsg::Graft {
+        id: id,
+        round: 1,
+        sender: n1.clone(),
+    });
+
+    assert_eq!(
+        sent(&actions),
+        vec![(
+            n1.addr,
+            PlumtreeMsg::Gossip {
+                id: id,
+                round: 1,
+                payload: vec![7],
+                sender: self_peer,
+            },
+        )]
+    );
+    assert!(plumtree.eager_push_peers.contains(&n1));
+}
+
+#[test]
+fn forget_announcements_of_removed_neighbour() {
+    let n1 = mock_peer();
+    let mut plumtree = new_plumtree(&[n1.clone()]);
+    let (id, _) = new_plumtree(&[]).broadcast(vec![7]);
+
+    plumtree.handle(PlumtreeMsg::IHave {
+        id: id,
+        round: 0,
+        sender: n1.clone(),
+    });
+    plumtree.neighbour_down(&n1);
+
+    assert!(plumtree.eager_push_peers.is_empty());
+    assert!(sent(&plumtree.handle_timer(Timer::Missing(id))).is_empty());
+}
+
+#[test]
+fn keep_strangers_out_of_the_tree() {
+    let (n1, stranger) = (mock_peer(), mock_peer());
+    let mut plumtree = new_plumtree(&[n1.clone()]);
+    let (id, _) = new_plumtree(&[]).broadcast(vec![7]);
+    let (other, _) = new_plumtree(&[]).broadcast(vec![8]);
+
+    // The broadcast itself is still welcome
+    let actions = plumtree.handle(PlumtreeMsg::Gossip {
+        id: id,
+        round: 0,
+        payload: vec![7],
+        sender: stranger.clone(),
+    });
+    assert!(actions.contains(&Action::Deliver(Delivery {
+        id: id,
+        payload: vec![7],
+    })));
+    plumtree.handle(PlumtreeMsg::Gossip {
+        id: id,
+        round: 0,
+        payload: vec![7],
+        sender: stranger.clone(),
+    });
+    plumtree.handle(PlumtreeMsg::Graft {
+        id: id,
+        round: 1,
+        sender: stranger.clone(),
+    });
+    let actions = plumtree.handle(PlumtreeMsg::IHave {
+        id: other,
+        round: 0,
+        sender: stranger.clone(),
+    });
+
+    assert!(actions.is_empty());
+    assert_eq!(plumtree.eager_push_peers.iter().collect::<Vec<_>>(), vec![&n1]);
+    assert!(plumtree.lazy_push_peers.is_empty());
+}
+
+#[test]
+fn forget_old_payloads_before_old_ids() {
+    let n1 = mock_peer();
+    let mut plumtree = new_plumtree(&[n1.clone()]);
+    let mut config = Config::default();
+    config.retained_payloads = 1;
+    config.retained_ids = 2;
+    plumtree.set_config(config);
+    let (first, _) = plumtree.broadcast(vec![1]);
+    plumtree.broadcast(vec![2]);
+
+    let graft = |id| PlumtreeMsg::Graft {
+        id: id,
+        round: 1,
+        sender: n1.clone(),
+    };
+    let gossip = |id, payload| PlumtreeMsg::Gossip {
+        id: id,
+        round: 1,
+        payload: payload,
+        sender: n1.clone(),
+    };
+
+    // The payload is gone, but a duplicate is still recognised
+    assert!(sent(&plumtree.handle(graft(first))).is_empty());
+    let actions = plumtree.handle(gossip(first, vec![1]));
+    assert!(!actions.iter().any(|a| match a {
+        Action::Deliver(_) => true,
+        _ => false,
+    }));
+
+    plumtree.broadcast(vec![3]);
+    let actions = plumtree.handle(gossip(first, vec![1]));
+    assert!(actions.contains(&Action::Deliver(Delivery {
+        id: first,
+        payload: vec![1],
+    })));
+}
+
+#[test]
+fn forward_gossip_of_the_last_round() {
+    let (origin, n1) = (mock_peer(), mock_peer());
+    let mut plumtree = new_plumtree(&[origin.clone(), n1.clone()]);
+    let mut remote = new_plumtree(&[]);
+    let (id, _) = remote.broadcast(vec![7]);
+
+    let actions = plumtree.handle(PlumtreeMsg::Gossip {
+        id: id,
+        round: u32::MAX,
+        payload: vec![7],
+        sender: origin.clone(),
+    });
+
+    let rounds: Vec<u32> = sent(&actions)
+        .into_iter()
+        .filter_map(|(_, msg)| match msg {
+            PlumtreeMsg::Gossip { round, .. } => Some(round),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(rounds, vec![u32::MAX]);
+}
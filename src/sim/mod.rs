@@ -0,0 +1,279 @@
+//! A discrete-event simulator that drives many `HyParView` state machines in a single thread.
+//! Time is virtual: the simulation jumps from one scheduled message or timer to the next, so hours
+//! of protocol activity among thousands of nodes take seconds to run, and a seed fixes the outcome.
+
+extern crate rand;
+
+use self::rand::rngs::SmallRng;
+use self::rand::{Rng, SeedableRng};
+use hpv::{expand_seed, Action, Config, ConfigError, HpvMsg, HyParView, Peer, PeerId, Timer, Views};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+mod network;
+pub use self::network::*;
+
+/// What happens at a point in virtual time
+enum Occurrence {
+    Deliver {
+        from: SocketAddr,
+        to: SocketAddr,
+        msg: HpvMsg,
+    },
+    Unreachable { at: SocketAddr, to: SocketAddr },
+    Fire { at: SocketAddr, timer: Timer },
+}
+
+struct Scheduled {
+    time: Duration,
+    // breaks ties between occurrences at the same time in the order they were scheduled
+    seq: u64,
+    occurrence: Occurrence,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Scheduled) -> bool {
+        (self.time, self.seq) == (other.time, other.seq)
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Scheduled) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Scheduled) -> Ordering {
+        (self.time, self.seq).cmp(&(other.time, other.seq))
+    }
+}
+
+/// Counts of what happened to the messages sent during a simulation
+#[derive(Eq, PartialEq, Clone, Copy, Default, Debug)]
+pub struct Stats {
+    pub sent: usize,
+    pub delivered: usize,
+    pub lost: usize,
+    /// Messages addressed to nodes that crashed or never existed
+    pub undeliverable: usize,
+}
+
+/// A cluster of `HyParView` nodes connected by a simulated `Network`
+pub struct Simulation {
+    now: Duration,
+    seq: u64,
+    queue: BinaryHeap<Reverse<Scheduled>>,
+    nodes: BTreeMap<SocketAddr, HyParView>,
+    // the number of nodes ever added, so that addresses of crashed nodes are not reused
+    added: u32,
+    network: Box<Network>,
+    // the time of the last delivery scheduled over each link, as messages between two nodes arrive
+    // in the order they were sent, like over a TCP connection
+    last_delivery: HashMap<(SocketAddr, SocketAddr), Duration>,
+    rng: SmallRng,
+    stats: Stats,
+}
+
+impl Simulation {
+    /// Creates an empty simulation; every random choice, including those of the nodes, derives from
+    /// `seed`
+    pub fn new(seed: u64, network: Box<Network>) -> Simulation {
+        Simulation {
+            now: Duration::from_secs(0),
+            seq: 0,
+            queue: BinaryHeap::new(),
+            nodes: BTreeMap::new(),
+            added: 0,
+            network: network,
+            last_delivery: HashMap::new(),
+            rng: SmallRng::from_seed(expand_seed(seed)),
+            stats: Stats::default(),
+        }
+    }
+
+    /// The current virtual time, measured from the start of the simulation
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Adds and starts a node with a fresh identity and address, returning its peer, or fails if
+    /// `config` is invalid
+    pub fn add_node(&mut self, mut config: Config) -> Result<Peer, ConfigError> {
+        self.added += 1;
+        let ip = Ipv4Addr::from(0x0A00_0000 + self.added);
+        let peer = Peer::new(
+            PeerId::random_from(&mut self.rng),
+            SocketAddr::new(IpAddr::V4(ip), 4000),
+        );
+        config.seed = Some(self.rng.gen());
+
+        let mut hpv = HyParView::new(peer.clone(), config)?;
+        let actions = hpv.start();
+        self.nodes.insert(peer.addr, hpv);
+        self.dispatch(peer.addr, actions);
+        Ok(peer)
+    }
+
+    /// Makes the node at `addr` join the overlay through `contact`
+    pub fn join(&mut self, addr: SocketAddr, contact: Peer) {
+        let actions = match self.nodes.get_mut(&addr) {
+            Some(hpv) => hpv.join(vec![contact.addr]),
+            None => return,
+        };
+        self.dispatch(addr, actions);
+    }
+
+    /// Lets the node leave the overlay gracefully, then removes it
+    pub fn leave(&mut self, addr: SocketAddr, handoff: bool) -> Option<HyParView> {
+        let actions = match self.nodes.get_mut(&addr) {
+            Some(hpv) => hpv.leave(handoff),
+            None => return None,
+        };
+        self.dispatch(addr, actions);
+        self.nodes.remove(&addr)
+    }
+
+    /// Removes the node without notifying anyone, as if its process died
+    pub fn crash(&mut self, addr: SocketAddr) -> Option<HyParView> {
+        self.nodes.remove(&addr)
+    }
+
+    pub fn node(&self, addr: &SocketAddr) -> Option<&HyParView> {
+        self.nodes.get(addr)
+    }
+
+    pub fn node_mut(&mut self, addr: &SocketAddr) -> Option<&mut HyParView> {
+        self.nodes.get_mut(addr)
+    }
+
+    pub fn addrs(&self) -> Vec<SocketAddr> {
+        self.nodes.keys().cloned().collect()
+    }
+
+    /// The views of all live nodes
+    pub fn views(&self) -> HashMap<PeerId, Views> {
+        self.nodes
+            .values()
+            .map(|hpv| (hpv.self_peer().id, Views::from_hyparview(hpv)))
+            .collect()
+    }
+
+    /// Processes the next occurrence, returning false if nothing is scheduled anymore
+    pub fn step(&mut self) -> bool {
+        match self.queue.pop() {
+            Some(Reverse(next)) => {
+                self.now = next.time;
+                self.occur(next.occurrence);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Processes every occurrence scheduled up to and including `time`, then advances the clock to
+    /// it
+    pub fn run_until(&mut self, time: Duration) {
+        loop {
+            match self.queue.peek() {
+                Some(&Reverse(ref next)) if next.time <= time => {}
+                _ => break,
+            }
+            self.step();
+        }
+        if self.now < time {
+            self.now = time;
+        }
+    }
+
+    pub fn run_for(&mut self, duration: Duration) {
+        let until = self.now + duration;
+        self.run_until(until);
+    }
+
+    fn occur(&mut self, occurrence: Occurrence) {
+        let (at, actions) = match occurrence {
+            Occurrence::Deliver { from, to, msg } => match self.nodes.get_mut(&to) {
+                Some(hpv) => {
+                    self.stats.delivered += 1;
+                    (to, hpv.handle(msg))
+                }
+                None => {
+                    // The recipient crashed while the message was underway
+                    self.stats.undeliverable += 1;
+                    let time = self.now;
+                    self.schedule(time, Occurrence::Unreachable { at: from, to: to });
+                    return;
+                }
+            },
+            Occurrence::Unreachable { at, to } => match self.nodes.get_mut(&at) {
+                Some(hpv) => (at, hpv.handle_unreachable(to)),
+                None => return,
+            },
+            Occurrence::Fire { at, timer } => match self.nodes.get_mut(&at) {
+                Some(hpv) => (at, hpv.handle_timer(timer)),
+                None => return,
+            },
+        };
+        self.dispatch(at, actions);
+    }
+
+    fn dispatch(&mut self, from: SocketAddr, actions: Vec<Action>) {
+        for action in actions {
+            match action {
+                Action::Send(to, msg) => self.transmit(from, to, msg),
+                Action::Schedule(timer, delay) => {
+                    let time = self.now + delay;
+                    self.schedule(time, Occurrence::Fire { at: from, timer: timer });
+                }
+                Action::Emit(_) => {}
+            }
+        }
+    }
+
+    fn transmit(&mut self, from: SocketAddr, to: SocketAddr, msg: HpvMsg) {
+        self.stats.sent += 1;
+        match self.network.transmit(from, to, &mut self.rng) {
+            Some(latency) => {
+                let time = self.now + latency;
+                if self.nodes.contains_key(&to) {
+                    let last = self.last_delivery.entry((from, to)).or_insert(time);
+                    *last = time.max(*last);
+                    let time = *last;
+                    let deliver = Occurrence::Deliver {
+                        from: from,
+                        to: to,
+                        msg: msg,
+                    };
+                    self.schedule(time, deliver);
+                } else {
+                    // Like a refused connection, the sender learns about it after a round trip
+                    self.stats.undeliverable += 1;
+                    let time = time + latency;
+                    self.schedule(time, Occurrence::Unreachable { at: from, to: to });
+                }
+            }
+            None => self.stats.lost += 1,
+        }
+    }
+
+    fn schedule(&mut self, time: Duration, occurrence: Occurrence) {
+        self.seq += 1;
+        self.queue.push(Reverse(Scheduled {
+            time: time,
+            seq: self.seq,
+            occurrence: occurrence,
+        }));
+    }
+}
+
+#[cfg(test)]
+mod test;
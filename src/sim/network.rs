@@ -0,0 +1,70 @@
+use super::rand::{Rng, RngCore};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Decides the fate of every message in a `Simulation`
+pub trait Network {
+    /// The time it takes for a message to travel between the addresses, or `None` if it is lost
+    fn transmit(&mut self, from: SocketAddr, to: SocketAddr, rng: &mut RngCore)
+        -> Option<Duration>;
+}
+
+/// Delivers every message after the same delay
+pub struct ConstantLatency(pub Duration);
+
+impl Network for ConstantLatency {
+    fn transmit(
+        &mut self,
+        _from: SocketAddr,
+        _to: SocketAddr,
+        _rng: &mut RngCore,
+    ) -> Option<Duration> {
+        Some(self.0)
+    }
+}
+
+/// Delivers every message after a delay drawn uniformly from `[min, max)`
+pub struct UniformLatency {
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl Network for UniformLatency {
+    fn transmit(
+        &mut self,
+        _from: SocketAddr,
+        _to: SocketAddr,
+        rng: &mut RngCore,
+    ) -> Option<Duration> {
+        if self.max <= self.min {
+            return Some(self.min);
+        }
+        let spread = nanos(self.max - self.min);
+        Some(self.min + Duration::from_nanos(rng.gen_range(0, spread)))
+    }
+}
+
+/// Drops each message with probability `loss`, and leaves the others to the wrapped `Network`
+pub struct Lossy<N: Network> {
+    pub network: N,
+    pub loss: f64,
+}
+
+impl<N: Network> Network for Lossy<N> {
+    fn transmit(
+        &mut self,
+        from: SocketAddr,
+        to: SocketAddr,
+        rng: &mut RngCore,
+    ) -> Option<Duration> {
+        if rng.gen::<f64>() < self.loss {
+            None
+        } else {
+            self.network.transmit(from, to, rng)
+        }
+    }
+}
+
+fn nanos(d: Duration) -> u64 {
+    d.as_secs() * 1_000_000_000 + u64::from(d.subsec_nanos())
+}
@@ -0,0 +1,252 @@
+use super::*;
+use hpv::AdaptiveSizing;
+use std::collections::{HashSet, VecDeque};
+
+fn latency() -> Box<Network> {
+    Box::new(UniformLatency {
+        min: Duration::from_millis(5),
+        max: Duration::from_millis(50),
+    })
+}
+
+/// Grows a cluster by letting every new node join through a random earlier one
+fn grow_cluster(sim: &mut Simulation, size: usize) -> Vec<Peer> {
+    grow_cluster_with(sim, size, Config::default())
+}
+
+fn grow_cluster_with(sim: &mut Simulation, size: usize, config: Config) -> Vec<Peer> {
+    let mut peers: Vec<Peer> = Vec::new();
+    for _ in 0..size {
+        let peer = sim.add_node(config.clone()).unwrap();
+        if !peers.is_empty() {
+            let contact = peers[sim.rng.gen_range(0, peers.len())].clone();
+            sim.join(peer.addr, contact);
+        }
+        peers.push(peer);
+        sim.run_for(Duration::from_millis(20));
+    }
+    peers
+}
+
+/// The number of nodes reachable from `start` over active links
+fn reachable(views: &HashMap<PeerId, Views>, start: PeerId) -> usize {
+    component(views, start).len()
+}
+
+/// The nodes reachable from `start` over active links
+fn component(views: &HashMap<PeerId, Views>, start: PeerId) -> HashSet<PeerId> {
+    let mut seen: HashSet<PeerId> = hashset!{start};
+    let mut queue: VecDeque<PeerId> = VecDeque::new();
+    queue.push_back(start);
+    while let Some(id) = queue.pop_front() {
+        for p in views[&id].active_view.iter() {
+            if views.contains_key(&p.id) && seen.insert(p.id) {
+                queue.push_back(p.id);
+            }
+        }
+    }
+    seen
+}
+
+/// The number of active links that the peer at the other end does not have in its active view
+fn one_sided_links(views: &HashMap<PeerId, Views>) -> usize {
+    views
+        .iter()
+        .map(|(id, v)| {
+            v.active_view
+                .iter()
+                .filter(|p| match views.get(&p.id) {
+                    Some(other) => !other.active_view.iter().any(|q| q.id == *id),
+                    None => true,
+                })
+                .count()
+        })
+        .sum()
+}
+
+#[test]
+fn advance_virtual_clock() {
+    let mut sim = Simulation::new(1, latency());
+    sim.add_node(Config::default()).unwrap();
+
+    sim.run_for(Duration::from_secs(3600));
+    assert_eq!(sim.now(), Duration::from_secs(3600));
+}
+
+#[test]
+fn refuse_nodes_with_invalid_config() {
+    let mut sim = Simulation::new(1, latency());
+    let mut invalid = Config::default();
+    invalid.max_passive_view_size = 0;
+
+    assert!(sim.add_node(invalid).is_err());
+    assert!(sim.add_node(Config::default()).is_ok());
+}
+
+#[test]
+fn form_connected_overlay() {
+    // Views sized for a thousand nodes, log10(1000) + 1 active and six times as many passive peers
+    let mut config = Config::default();
+    config.max_active_view_size = 4;
+    config.max_passive_view_size = 24;
+    let mut sim = Simulation::new(7, latency());
+    let peers = grow_cluster_with(&mut sim, 1000, config.clone());
+    // Well past the rounds in which failure detection and shuffles reshape the overlay
+    let window = config.heartbeat_interval * (config.max_missed_heartbeats as u32 + 1);
+    sim.run_for(window * 3 + config.shuffle_interval * 5);
+
+    let views = sim.views();
+    assert_eq!(views.len(), 1000);
+    assert_eq!(reachable(&views, peers[0].id), 1000);
+    assert_eq!(one_sided_links(&views), 0);
+}
+
+#[test]
+fn keep_active_views_symmetric() {
+    let config = Config::default();
+    let mut sim = Simulation::new(11, latency());
+    let peers = grow_cluster(&mut sim, 200);
+    // Before any silent active peer could be considered failed
+    sim.run_for(Duration::from_secs(1));
+    assert_eq!(one_sided_links(&sim.views()), 0);
+
+    // Well past the heartbeat rounds after which one-sided links would break
+    let window = config.heartbeat_interval * (config.max_missed_heartbeats as u32 + 1);
+    sim.run_for(window * 5);
+    let views = sim.views();
+    assert_eq!(one_sided_links(&views), 0);
+    assert_eq!(reachable(&views, peers[0].id), 200);
+}
+
+#[test]
+fn same_seed_yields_same_overlay() {
+    let run = |seed| {
+        let mut sim = Simulation::new(seed, latency());
+        grow_cluster(&mut sim, 50);
+        sim.run_for(Duration::from_secs(60));
+        (sim.views(), sim.stats())
+    };
+
+    assert_eq!(run(3), run(3));
+}
+
+#[test]
+fn drop_all_messages_on_total_loss() {
+    let network = Lossy {
+        network: UniformLatency {
+            min: Duration::from_millis(5),
+            max: Duration::from_millis(50),
+        },
+        loss: 1.0,
+    };
+    let mut sim = Simulation::new(1, Box::new(network));
+    grow_cluster(&mut sim, 10);
+    sim.run_for(Duration::from_secs(60));
+
+    let stats = sim.stats();
+    assert!(stats.sent > 0);
+    assert_eq!(stats.lost, stats.sent);
+    assert_eq!(stats.delivered, 0);
+}
+
+#[test]
+fn repair_overlay_after_crashes() {
+    let mut sim = Simulation::new(11, latency());
+    let peers = grow_cluster(&mut sim, 200);
+    sim.run_for(Duration::from_secs(60));
+
+    let crashed: Vec<Peer> = peers.iter().skip(1).step_by(10).cloned().collect();
+    for p in crashed.iter() {
+        sim.crash(p.addr);
+    }
+    // Heartbeats reveal the crashed peers within a few rounds
+    sim.run_for(Duration::from_secs(60));
+
+    let views = sim.views();
+    assert_eq!(views.len(), 180);
+    for v in views.values() {
+        assert!(crashed.iter().all(|p| !v.active_view.contains(p)));
+    }
+    assert_eq!(reachable(&views, peers[0].id), 180);
+}
+
+#[test]
+fn replace_leaving_peers_immediately() {
+    let mut sim = Simulation::new(13, latency());
+    let peers = grow_cluster(&mut sim, 50);
+    sim.run_for(Duration::from_secs(10));
+
+    let leaving = peers[1].clone();
+    sim.leave(leaving.addr, true);
+    // Well within a heartbeat interval, so only the leave itself can explain the removal
+    sim.run_for(Duration::from_secs(1));
+
+    for v in sim.views().values() {
+        assert!(!v.active_view.contains(&leaving));
+    }
+}
+
+#[test]
+fn bootstrap_from_seeds() {
+    let mut sim = Simulation::new(17, latency());
+    let seed = sim.add_node(Config::default()).unwrap();
+    let mut config = Config::default();
+    config.seed_addrs = vec![seed.addr];
+    for _ in 0..100 {
+        // Joins without any explicit contact
+        sim.add_node(config.clone()).unwrap();
+        sim.run_for(Duration::from_millis(20));
+    }
+    // Nodes the seed drops for newcomers reconnect through it on their next shuffle round
+    sim.run_for(Duration::from_secs(60));
+
+    let views = sim.views();
+    assert_eq!(reachable(&views, seed.id), 101);
+}
+
+#[test]
+fn adapt_view_sizes_to_estimated_size() {
+    let mut sim = Simulation::new(19, latency());
+    let mut config = Config::default();
+    config.adaptive_sizing = Some(AdaptiveSizing::default());
+    let peers = grow_cluster_with(&mut sim, 150, config);
+    // Sketches spread by one hop per shuffle round
+    sim.run_for(Duration::from_secs(600));
+
+    let views = sim.views();
+    assert_eq!(reachable(&views, peers[0].id), 150);
+    // log10(150) rounds to 2, so every node should settle on 2 + 1 active and 6 * 3 passive peers
+    for addr in sim.addrs() {
+        let hpv = sim.node(&addr).unwrap();
+        let estimate = hpv.estimated_size();
+        assert!(estimate > 100 && estimate < 225, "estimated {} peers", estimate);
+        assert_eq!(hpv.config().max_active_view_size, 3);
+        assert_eq!(hpv.config().max_passive_view_size, 18);
+    }
+}
+
+#[test]
+fn shrink_estimate_after_half_the_peers_leave() {
+    let mut sim = Simulation::new(23, latency());
+    let mut config = Config::default();
+    config.adaptive_sizing = Some(AdaptiveSizing::default());
+    let peers = grow_cluster_with(&mut sim, 200, config);
+    sim.run_for(Duration::from_secs(600));
+    for addr in sim.addrs() {
+        let estimate = sim.node(&addr).unwrap().estimated_size();
+        assert!(estimate > 130 && estimate < 300, "estimated {} peers", estimate);
+    }
+
+    for p in peers.iter().skip(1).step_by(2) {
+        sim.leave(p.addr, true);
+    }
+    // The departed peers drop out of the estimate once two new epochs have started
+    let epoch = AdaptiveSizing::default().sketch_epoch_rounds;
+    sim.run_for(Config::default().shuffle_interval * (2 * epoch + 2));
+
+    assert_eq!(sim.addrs().len(), 100);
+    for addr in sim.addrs() {
+        let estimate = sim.node(&addr).unwrap().estimated_size();
+        assert!(estimate > 65 && estimate < 150, "estimated {} peers", estimate);
+    }
+}